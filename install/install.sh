@@ -455,6 +455,22 @@ configuration may lock you our of your system.
   done
 
   ARCH=$(/usr/bin/uname -m)
+  if [[ $ARCH == "x86_64" ]];then
+    cp "$ROOT"/x86_64/rauthy-token /usr/bin/
+  elif [[ $ARCH == "aarch64" || $ARCH == "arm64" ]]; then
+    cp "$ROOT"/aarch64/rauthy-token /usr/bin/
+  fi
+  chmod 755 /usr/bin/rauthy-token
+  restorecon /usr/bin/rauthy-token
+
+  if [[ $ARCH == "x86_64" ]];then
+    cp "$ROOT"/x86_64/rauthy-whoami /usr/bin/
+  elif [[ $ARCH == "aarch64" || $ARCH == "arm64" ]]; then
+    cp "$ROOT"/aarch64/rauthy-whoami /usr/bin/
+  fi
+  chmod 755 /usr/bin/rauthy-whoami
+  restorecon /usr/bin/rauthy-whoami
+
   if [[ $ARCH == "x86_64" ]];then
 
     if is_rhel; then
@@ -1,16 +1,34 @@
 use libnss::{libnss_group_hooks, libnss_host_hooks, libnss_passwd_hooks};
 use log::LevelFilter;
-use std::process;
+use std::sync::LazyLock;
+use std::{fs, process};
 use syslog::{BasicLogger, Facility, Formatter3164};
 
-mod api_types;
 mod nss;
+mod snapshot;
 mod uds;
 
 #[cfg(debug_assertions)]
-static PROXY_SOCKET: &str = "/tmp/rauthy/rauthy_proxy.sock";
+static NSS_SOCKET: &str = "/tmp/rauthy/rauthy_nss.sock";
 #[cfg(not(debug_assertions))]
-static PROXY_SOCKET: &str = "/run/rauthy/rauthy_proxy.sock";
+static NSS_SOCKET: &str = "/run/rauthy/rauthy_nss.sock";
+#[cfg(debug_assertions)]
+static SNAPSHOT_PATH: &str = "/tmp/rauthy/nss.snapshot";
+#[cfg(not(debug_assertions))]
+static SNAPSHOT_PATH: &str = "/run/rauthy/nss.snapshot";
+#[cfg(debug_assertions)]
+static ID_FLOOR_PATH: &str = "/tmp/rauthy/id_floor";
+#[cfg(not(debug_assertions))]
+static ID_FLOOR_PATH: &str = "/run/rauthy/id_floor";
+
+/// Rauthy never hands out ids below this value, so we can skip the proxy request for them.
+/// The proxy shares its configured `id_floor` on startup.
+static ID_FLOOR: LazyLock<u32> = LazyLock::new(|| {
+    fs::read_to_string(ID_FLOOR_PATH)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(100_000)
+});
 
 pub struct RauthyNss;
 
@@ -1,14 +1,14 @@
-use crate::api_types::GetentResponse;
-use crate::{RauthyNss, init_syslog, send_getent};
+use crate::{ID_FLOOR, RauthyNss, init_syslog, send_getent};
 use libc::gid_t;
 use libnss::group::{Group, GroupHooks};
 use libnss::interop::Response;
+use rauthy_nss_proto::{Getent, GetentResponse};
 
 impl GroupHooks for RauthyNss {
     fn get_all_entries() -> Response<Vec<Group>> {
         init_syslog();
 
-        match send_getent!("/getent/groups") {
+        match send_getent!(Getent::Groups) {
             GetentResponse::Groups(groups) => Response::Success(
                 groups
                     .into_iter()
@@ -25,14 +25,13 @@ impl GroupHooks for RauthyNss {
     }
 
     fn get_entry_by_gid(gid: gid_t) -> Response<Group> {
-        // Rauthys ids start all at 100_000
-        if gid < 100_000 {
+        if gid < *ID_FLOOR {
             return Response::NotFound;
         }
 
         init_syslog();
 
-        match send_getent!(&format!("/getent/groups/gid/{gid}")) {
+        match send_getent!(Getent::GroupId(gid)) {
             GetentResponse::Group(group) => Response::Success(Group {
                 name: group.name,
                 passwd: "x".to_string(),
@@ -46,7 +45,7 @@ impl GroupHooks for RauthyNss {
     fn get_entry_by_name(name: String) -> Response<Group> {
         init_syslog();
 
-        match send_getent!(&format!("/getent/groups/name/{name}")) {
+        match send_getent!(Getent::Groupname(name)) {
             GetentResponse::Group(group) => Response::Success(Group {
                 name: group.name,
                 passwd: "x".to_string(),
@@ -1,15 +1,15 @@
 use crate::RauthyNss;
-use crate::api_types::GetentResponse;
 use crate::{init_syslog, send_getent};
 use libnss::host::{AddressFamily, Addresses, Host, HostHooks};
 use libnss::interop::Response;
+use rauthy_nss_proto::{Getent, GetentResponse};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 impl HostHooks for RauthyNss {
     fn get_all_entries() -> Response<Vec<Host>> {
         init_syslog();
 
-        match send_getent!("/getent/hosts") {
+        match send_getent!(Getent::Hosts) {
             GetentResponse::Hosts(hosts) => {
                 let mut res = Vec::with_capacity(hosts.len());
 
@@ -31,7 +31,7 @@ impl HostHooks for RauthyNss {
     fn get_host_by_name(name: &str, family: AddressFamily) -> Response<Host> {
         init_syslog();
 
-        match send_getent!(&format!("/getent/hosts/name/{name}")) {
+        match send_getent!(Getent::Hostname(name.to_string())) {
             GetentResponse::Host(host) => {
                 let (v4, v6) = split_addrs(host.addresses);
                 let addresses = match family {
@@ -56,7 +56,7 @@ impl HostHooks for RauthyNss {
     fn get_host_by_addr(addr: IpAddr) -> Response<Host> {
         init_syslog();
 
-        match send_getent!(&format!("/getent/hosts/ip/{addr}")) {
+        match send_getent!(Getent::HostIp(addr)) {
             GetentResponse::Host(host) => {
                 let (v4, v6) = split_addrs(host.addresses);
                 Response::Success(Host {
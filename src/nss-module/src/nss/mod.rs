@@ -1,50 +1,64 @@
-use std::sync::LazyLock;
-
 mod group;
 mod hosts;
+mod netgroup;
 mod passwd;
 
-static RT: LazyLock<tokio::runtime::Runtime> = LazyLock::new(|| {
-    tokio::runtime::Builder::new_current_thread()
-        .worker_threads(1)
-        .enable_all()
-        .build()
-        .expect("Cannot build tokio runtime")
-});
-
 #[macro_export]
 macro_rules! send_getent {
-    ($path:expr) => {
-        match $crate::nss::RT.block_on(async {
-            let (status, body) = match $crate::uds::get($path).await {
-                Ok(r) => r,
-                Err(err) => {
-                    log::error!("Error connecting to UDS: {}", err);
-                    return Err(libnss::interop::Response::TryAgain);
-                }
-            };
+    ($getent:expr) => {{
+        let getent = $getent;
+        if let Some(resp) = $crate::snapshot::lookup(&getent) {
+            resp
+        } else {
+            $crate::send_getent!(@uds getent)
+        }
+    }};
+    (@uds $getent:ident) => {{
+        let (status, payload) = match $crate::uds::getent(&$getent) {
+            Ok(r) => r,
+            Err(err) if $crate::uds::is_unreachable(&err) => {
+                // the proxy is not running at all
+                log::error!("Error connecting to UDS: {}", err);
+                return libnss::interop::Response::Unavail;
+            }
+            Err(err) if err.downcast_ref::<std::io::Error>().is_some() => {
+                // a timeout or broken connection, the next lookup may very well succeed
+                log::warn!("getent request to UDS failed: {}", err);
+                unsafe { *libc::__errno_location() = libc::EAGAIN };
+                return libnss::interop::Response::TryAgain;
+            }
+            Err(err) => {
+                log::error!("Invalid getent response from UDS: {}", err);
+                return libnss::interop::Response::Unavail;
+            }
+        };
 
-            if status.is_success() {
-                match bincode::decode_from_slice::<$crate::api_types::GetentResponse, _>(
-                    body.as_ref(),
-                    bincode::config::standard(),
-                ) {
-                    Ok((resp, _)) => Ok(resp),
+        match status {
+            rauthy_nss_proto::Status::Ok => {
+                match rauthy_nss_proto::decode_getent_response(&payload) {
+                    Ok(resp) => resp,
                     Err(err) => {
                         log::error!("Error decoding getent response: {}", err);
-                        Err(libnss::interop::Response::Unavail)
+                        return libnss::interop::Response::Unavail;
                     }
                 }
-            } else {
-                let text = String::from_utf8_lossy(body.as_ref());
+            }
+            rauthy_nss_proto::Status::NotFound => {
+                return libnss::interop::Response::NotFound;
+            }
+            rauthy_nss_proto::Status::Unavailable => {
+                let text = String::from_utf8_lossy(&payload);
                 log::error!("getent request failed: {}", text);
-                Err(libnss::interop::Response::Unavail)
+                return libnss::interop::Response::Unavail;
             }
-        }) {
-            Ok(resp) => resp,
-            Err(err) => {
-                return err;
+            rauthy_nss_proto::Status::TryAgain => {
+                let text = String::from_utf8_lossy(&payload);
+                log::warn!("getent request failed temporarily: {}", text);
+                // glibc checks `errno` for `NSS_STATUS_TRYAGAIN`, and a stale `ERANGE`
+                // would make it retry with bigger buffers over and over.
+                unsafe { *libc::__errno_location() = libc::EAGAIN };
+                return libnss::interop::Response::TryAgain;
             }
         }
-    };
+    }};
 }
@@ -0,0 +1,188 @@
+//! `libnss` does not provide hooks for the netgroup database, so we implement the glibc
+//! interface for `setnetgrent` / `getnetgrent_r` / `endnetgrent` ourselves.
+//!
+//! Netgroups are derived from Rauthy `host` groups. Each host in the group becomes a
+//! `(host,-,)` triple and each user with access a `(-,user,)` triple, which is exactly what
+//! `sudoers` and NFS `exports` need for `@netgroup` rules.
+
+use crate::{init_syslog, send_getent};
+use libc::{c_char, c_int, c_ulong, c_void, size_t};
+use libnss::interop::{NssStatus, Response};
+use rauthy_nss_proto::{Getent, GetentResponse};
+use std::ffi::CStr;
+use std::ptr;
+
+/// `enum { triple_val, group_val }` from glibc's `netgroup.h`
+const TRIPLE_VAL: c_int = 0;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CTriple {
+    host: *const c_char,
+    user: *const c_char,
+    domain: *const c_char,
+}
+
+#[repr(C)]
+union CVal {
+    triple: CTriple,
+    group: *const c_char,
+}
+
+/// Mirrors `struct __netgrent` from glibc. We only ever touch `typ`, `val`, `data` and
+/// `position`, everything else belongs to glibc.
+#[repr(C)]
+pub struct CNetgrent {
+    typ: c_int,
+    val: CVal,
+    data: *mut c_char,
+    data_size: size_t,
+    // union with `char *cursor`, which has the same size
+    position: c_ulong,
+    first: c_int,
+    known_groups: *mut c_void,
+    needed_groups: *mut c_void,
+    nip: *mut c_void,
+}
+
+/// One netgroup triple. `None` means "-", which matches nothing, in contrast to an empty
+/// field, which would be a wildcard.
+struct Triple {
+    host: Option<String>,
+    user: Option<String>,
+}
+
+fn fetch_triples(name: String) -> Response<Vec<Triple>> {
+    init_syslog();
+
+    match send_getent!(Getent::Netgroupname(name)) {
+        GetentResponse::Netgroup(ng) => {
+            let hosts = ng.hosts.into_iter().map(|host| Triple {
+                host: Some(host),
+                user: None,
+            });
+            let users = ng.users.into_iter().map(|user| Triple {
+                host: None,
+                user: Some(user),
+            });
+            Response::Success(hosts.chain(users).collect())
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Copies `value` NUL-terminated into `buf` at `*pos` and returns a pointer to it.
+unsafe fn write_str(
+    buf: *mut c_char,
+    buflen: size_t,
+    pos: &mut usize,
+    value: &str,
+) -> Option<*const c_char> {
+    let len = value.len();
+    if value.contains('\0') || *pos + len + 1 > buflen {
+        return None;
+    }
+
+    unsafe {
+        let start = buf.add(*pos);
+        ptr::copy_nonoverlapping(value.as_ptr() as *const c_char, start, len);
+        *start.add(len) = 0;
+        *pos += len + 1;
+        Some(start)
+    }
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _nss_rauthy_setnetgrent(
+    group: *const c_char,
+    result: *mut CNetgrent,
+) -> c_int {
+    if group.is_null() || result.is_null() {
+        return NssStatus::Unavail as c_int;
+    }
+
+    let name = match unsafe { CStr::from_ptr(group) }.to_str() {
+        Ok(name) => name.to_string(),
+        Err(_) => return NssStatus::NotFound as c_int,
+    };
+
+    match fetch_triples(name) {
+        // `data` is always NULL here, glibc calls `endnetgrent` of the previous service first
+        Response::Success(triples) => unsafe {
+            (*result).data = Box::into_raw(Box::new(triples)) as *mut c_char;
+            (*result).position = 0;
+            NssStatus::Success as c_int
+        },
+        // `errno` has already been set to `EAGAIN` by `send_getent!` for `TryAgain`
+        resp => resp.to_status() as c_int,
+    }
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _nss_rauthy_getnetgrent_r(
+    result: *mut CNetgrent,
+    buffer: *mut c_char,
+    buflen: size_t,
+    errnop: *mut c_int,
+) -> c_int {
+    if result.is_null() || unsafe { (*result).data.is_null() } {
+        return NssStatus::Unavail as c_int;
+    }
+
+    unsafe {
+        let triples = &*((*result).data as *const Vec<Triple>);
+        let Some(triple) = triples.get((*result).position as usize) else {
+            return NssStatus::NotFound as c_int;
+        };
+
+        let mut pos = 0;
+        let host = write_str(
+            buffer,
+            buflen,
+            &mut pos,
+            triple.host.as_deref().unwrap_or("-"),
+        );
+        let user = write_str(
+            buffer,
+            buflen,
+            &mut pos,
+            triple.user.as_deref().unwrap_or("-"),
+        );
+        let (Some(host), Some(user)) = (host, user) else {
+            // glibc retries with a bigger buffer
+            *errnop = libc::ERANGE;
+            return NssStatus::TryAgain as c_int;
+        };
+
+        (*result).typ = TRIPLE_VAL;
+        (*result).val.triple = CTriple {
+            host,
+            user,
+            // wildcard - Rauthy does not know about NIS domains
+            domain: ptr::null(),
+        };
+        (*result).position += 1;
+    }
+
+    NssStatus::Success as c_int
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _nss_rauthy_endnetgrent(result: *mut CNetgrent) -> c_int {
+    if !result.is_null() {
+        unsafe { free_data(result) };
+    }
+    NssStatus::Success as c_int
+}
+
+/// Drops the triples we stored in `data` during `setnetgrent`.
+unsafe fn free_data(result: *mut CNetgrent) {
+    unsafe {
+        if !(*result).data.is_null() {
+            drop(Box::from_raw((*result).data as *mut Vec<Triple>));
+            (*result).data = ptr::null_mut();
+            (*result).data_size = 0;
+            (*result).position = 0;
+        }
+    }
+}
@@ -1,14 +1,14 @@
-use crate::api_types::GetentResponse;
-use crate::init_syslog;
+use crate::{ID_FLOOR, init_syslog};
 use crate::{RauthyNss, send_getent};
 use libnss::interop::Response;
 use libnss::passwd::{Passwd, PasswdHooks};
+use rauthy_nss_proto::{Getent, GetentResponse};
 
 impl PasswdHooks for RauthyNss {
     fn get_all_entries() -> Response<Vec<Passwd>> {
         init_syslog();
 
-        match send_getent!("/getent/users") {
+        match send_getent!(Getent::Users) {
             GetentResponse::Users(users) => {
                 let mut res = Vec::with_capacity(users.len());
 
@@ -32,14 +32,13 @@ impl PasswdHooks for RauthyNss {
     }
 
     fn get_entry_by_uid(uid: libc::uid_t) -> Response<Passwd> {
-        // Rauthys ids start all at 100_000
-        if uid < 100_000 {
+        if uid < *ID_FLOOR {
             return Response::NotFound;
         }
 
         init_syslog();
 
-        match send_getent!(&format!("/getent/users/uid/{uid}")) {
+        match send_getent!(Getent::UserId(uid)) {
             GetentResponse::User(user) => {
                 let dir = format!("/home/{}", user.name);
                 Response::Success(Passwd {
@@ -59,7 +58,7 @@ impl PasswdHooks for RauthyNss {
     fn get_entry_by_name(name: String) -> Response<Passwd> {
         init_syslog();
 
-        match send_getent!(&format!("/getent/users/name/{name}")) {
+        match send_getent!(Getent::Username(name)) {
             GetentResponse::User(user) => {
                 let dir = format!("/home/{}", user.name);
                 Response::Success(Passwd {
@@ -0,0 +1,119 @@
+use crate::SNAPSHOT_PATH;
+use rauthy_nss_proto::{
+    Getent, GetentResponse, SNAPSHOT_HEADER_LEN, SNAPSHOT_STALE_OFFSET, Snapshot,
+};
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{ptr, slice};
+
+/// How often we look for a snapshot again, if it is missing, broken or expired. This keeps
+/// the `open()` off the hot path, when the proxy does not publish any.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+static STATE: Mutex<State> = Mutex::new(State {
+    mapping: None,
+    last_try: None,
+});
+
+struct State {
+    mapping: Option<Mapping>,
+    last_try: Option<Instant>,
+}
+
+/// A read-only mapping of the snapshot file. The proxy never modifies a published file
+/// apart from the stale flag. It writes a new one and swaps it in with a `rename()`, which
+/// keeps this mapping intact.
+struct Mapping {
+    ptr: *const u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and only accessed while holding the `STATE` lock
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn open() -> io::Result<Self> {
+        let file = File::open(SNAPSHOT_PATH)?;
+        let len = file.metadata()?.len() as usize;
+        if len < SNAPSHOT_HEADER_LEN {
+            return Err(io::Error::from(ErrorKind::InvalidData));
+        }
+
+        // SAFETY: a fresh mapping of a valid fd, which is checked for errors below
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: `ptr` is valid for `len` bytes until we unmap it on drop
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Set by the proxy after it has published a newer snapshot.
+    fn is_stale(&self) -> bool {
+        // SAFETY: the header has been checked for its length and the flag is 4 byte aligned
+        let flag = unsafe { &*(self.ptr.add(SNAPSHOT_STALE_OFFSET) as *const AtomicU32) };
+        flag.load(Ordering::Acquire) != 0
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: no references into the mapping can outlive the `STATE` lock
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Resolves `getent` from the snapshot without any IPC. `None` means the caller must ask
+/// the proxy, because it may still exist.
+pub fn lookup(getent: &Getent) -> Option<GetentResponse> {
+    // never block, e.g. if the lock was held during a `fork()`
+    let mut state = STATE.try_lock().ok()?;
+
+    if state.mapping.as_ref().is_some_and(|m| m.is_stale()) {
+        state.mapping = None;
+    }
+    if state.mapping.is_none() {
+        if state
+            .last_try
+            .is_some_and(|last| last.elapsed() < RETRY_INTERVAL)
+        {
+            return None;
+        }
+        state.last_try = Some(Instant::now());
+        state.mapping = Mapping::open().ok();
+    }
+
+    let snapshot = Snapshot::parse(state.mapping.as_ref()?.bytes()).ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    if snapshot.expires() < now {
+        // The proxy may have been restarted and created a new file without flagging this
+        // one, or it cannot reach Rauthy anymore. Look for a new one after the interval.
+        state.mapping = None;
+        return None;
+    }
+
+    snapshot.get(getent)
+}
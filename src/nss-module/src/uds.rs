@@ -1,30 +1,139 @@
-use crate::PROXY_SOCKET;
-use http_body_util::BodyExt;
-use hyper::body::Bytes;
-use hyper::http::{Method, Request, StatusCode};
-use hyper_util::rt::TokioIo;
-use tokio::net::UnixStream;
-
-// not a very efficient impl, because it opens the TCP stream each time, but
-// absolutely fine when we only need to do a single request all the time anyway
-pub async fn get(path: &str) -> anyhow::Result<(StatusCode, Bytes)> {
-    let stream = TokioIo::new(UnixStream::connect(PROXY_SOCKET).await?);
-    let (mut sender, conn) = hyper::client::conn::http1::handshake(stream).await?;
-    tokio::task::spawn(async move {
-        if let Err(err) = conn.await {
-            eprintln!("Connection failed: {err:?}");
+use crate::NSS_SOCKET;
+use rauthy_nss_proto::{
+    Getent, HEADER_LEN, HELLO_LEN, PROTOCOL_VERSION, ProtoError, Status, decode_response,
+    encode_request, hello, parse_header, parse_hello,
+};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Hard limit for a single request including connect. A hung proxy must never be able to
+/// freeze something like an `ls -l`.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The keep-alive connection of this process. It is never used after a `fork()`, because
+/// parent and child would read each other's responses from the same socket.
+static CONN: Mutex<Option<Conn>> = Mutex::new(None);
+
+struct Conn {
+    stream: UnixStream,
+    pid: u32,
+}
+
+impl Conn {
+    /// Connects and does the version handshake.
+    fn connect(deadline: Instant) -> anyhow::Result<Self> {
+        let mut conn = Self {
+            stream: UnixStream::connect(NSS_SOCKET)?,
+            pid: process::id(),
+        };
+
+        conn.stream.set_write_timeout(Some(remaining(deadline)?))?;
+        conn.stream.write_all(&hello())?;
+        let mut buf = [0; HELLO_LEN];
+        read_exact(&mut conn, &mut buf, deadline)?;
+        let version = parse_hello(&buf)?;
+        if version != PROTOCOL_VERSION {
+            return Err(ProtoError::Version(version).into());
+        }
+
+        Ok(conn)
+    }
+}
+
+/// A synchronous getent request against the proxy, which re-uses the connection across
+/// calls within the same process. Returns the status and payload.
+pub fn getent(getent: &Getent) -> anyhow::Result<(Status, Vec<u8>)> {
+    let req = encode_request(getent)?;
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+
+    // A lock that is held by another thread - or has been held while this process was
+    // forked and will therefore never be released - must never block us. Fall back to a
+    // one-shot connection in that case.
+    let mut guard = match CONN.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            let mut conn = Conn::connect(deadline)?;
+            return request(&mut conn, &req, deadline);
+        }
+    };
+
+    if let Some(conn) = guard.as_mut() {
+        if conn.pid == process::id() {
+            match request(conn, &req, deadline) {
+                Ok(resp) => return Ok(resp),
+                // The proxy may have closed an idle connection or restarted in between.
+                // Retry exactly once with a fresh one.
+                Err(err) if Instant::now() < deadline => {
+                    log::debug!("Re-connecting to {NSS_SOCKET}: {err}");
+                }
+                Err(err) => {
+                    *guard = None;
+                    return Err(err);
+                }
+            }
+        } else {
+            // Dropping only closes our copy of the fd, the parent's connection stays intact.
+            *guard = None;
+        }
+    }
+
+    let mut conn = Conn::connect(deadline)?;
+    match request(&mut conn, &req, deadline) {
+        Ok(resp) => {
+            *guard = Some(conn);
+            Ok(resp)
         }
-    });
+        Err(err) => {
+            *guard = None;
+            Err(err)
+        }
+    }
+}
+
+/// Whether the proxy cannot be reached at all, because the socket does not exist or nobody
+/// is listening on it. Any other error happened on an established connection.
+pub fn is_unreachable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<io::Error>().is_some_and(|err| {
+        matches!(
+            err.kind(),
+            ErrorKind::NotFound | ErrorKind::ConnectionRefused
+        )
+    })
+}
+
+fn request(conn: &mut Conn, req: &[u8], deadline: Instant) -> anyhow::Result<(Status, Vec<u8>)> {
+    conn.stream.set_write_timeout(Some(remaining(deadline)?))?;
+    conn.stream.write_all(req)?;
+
+    let mut header = [0; HEADER_LEN];
+    read_exact(conn, &mut header, deadline)?;
+    let mut body = vec![0; parse_header(&header)?];
+    read_exact(conn, &mut body, deadline)?;
 
-    let body: http_body_util::Empty<Bytes> = http_body_util::Empty::new();
-    let request = Request::builder()
-        .method(Method::GET)
-        .uri(path)
-        .body(body)?;
+    let (status, payload) = decode_response(&body)?;
+    Ok((status, payload.to_vec()))
+}
 
-    let res = sender.send_request(request).await?;
-    let status = res.status();
-    let body = res.collect().await?.to_bytes();
+fn read_exact(conn: &mut Conn, mut buf: &mut [u8], deadline: Instant) -> anyhow::Result<()> {
+    // update the timeout with each read, so a slowly trickling response cannot exceed it
+    while !buf.is_empty() {
+        conn.stream.set_read_timeout(Some(remaining(deadline)?))?;
+        match conn.stream.read(buf)? {
+            0 => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+            n => buf = &mut buf[n..],
+        }
+    }
+    Ok(())
+}
 
-    Ok((status, body))
+fn remaining(deadline: Instant) -> anyhow::Result<Duration> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    // `set_*_timeout()` rejects a zero duration anyway
+    if left.is_zero() {
+        return Err(io::Error::from(ErrorKind::TimedOut).into());
+    }
+    Ok(left)
 }
@@ -30,6 +30,22 @@ pub struct PamPreflightResponse {
     pub mfa_required: bool,
 }
 
+#[derive(Debug, Serialize)]
+pub struct PamOidcTokenRequest<'a> {
+    pub host_id: &'a str,
+    pub host_secret: &'a str,
+    pub client_id: &'a str,
+    pub scope: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcTokenSet {
+    pub access_token: String,
+    pub expires_in: i64,
+    pub id_token: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct PamMfaStartRequest {
     pub username: String,
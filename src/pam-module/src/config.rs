@@ -1,4 +1,4 @@
-use crate::pam::sys_err;
+use crate::pam::{PamService, sys_err};
 use pamsm::{Pam, PamError};
 use serde::Deserialize;
 use std::fs;
@@ -21,9 +21,43 @@ pub struct Config {
     pub host_secret: String,
     #[serde(default = "data_path")]
     pub data_path: PathBuf,
+    #[serde(default)]
+    pub token_store: TokenStoreType,
     pub home_dir_skel: Option<PathBuf>,
     pub exec_session_open: Option<PathBuf>,
     pub exec_session_close: Option<PathBuf>,
+    #[serde(default = "exec_session_services")]
+    pub exec_session_open_services: Vec<PamService>,
+    #[serde(default = "exec_session_services")]
+    pub exec_session_close_services: Vec<PamService>,
+    #[serde(default = "hook_timeout")]
+    pub hook_timeout: u64,
+    pub hook_user: Option<String>,
+    pub session_token_client_id: Option<String>,
+    #[serde(default = "session_token_scope")]
+    pub session_token_scope: String,
+    #[serde(default = "id_floor")]
+    pub id_floor: u32,
+    #[serde(default)]
+    pub subid_enable: bool,
+    #[serde(default = "subid_base")]
+    pub subid_base: u32,
+    #[serde(default = "subid_count")]
+    pub subid_count: u32,
+    pub access_policy: Option<PathBuf>,
+}
+
+/// Where `PamToken`s are stored between the PAM stages and sessions.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenStoreType {
+    /// `<data_path>/<username>/token`, survives reboots
+    #[default]
+    File,
+    /// `/run/rauthy/pam/<username>/token`, cleared on reboot
+    Tmpfs,
+    /// the kernel keyring of `root`, never touches any disk
+    Keyring,
 }
 
 #[inline]
@@ -31,24 +65,44 @@ fn data_path() -> PathBuf {
     "/var/lib/pam_rauthy".into()
 }
 
-impl Config {
-    #[inline]
-    pub fn data_path_user(&self, pamh: &Pam, username: &str) -> anyhow::Result<PathBuf> {
-        let path = self.data_path.join(username);
-
-        fs::create_dir_all(&path).inspect_err(|err| {
-            sys_err(pamh, &format!("Error creating path for config file: {err}"));
-        })?;
-        fs::set_permissions(&path, Permissions::from_mode(0o600)).inspect_err(|err| {
-            sys_err(
-                pamh,
-                &format!("Error setting permissions for config file: {err}"),
-            );
-        })?;
-
-        Ok(path)
-    }
+#[inline]
+fn exec_session_services() -> Vec<PamService> {
+    vec![
+        PamService::Login,
+        PamService::Ssh,
+        PamService::Gdm,
+        PamService::Sddm,
+        PamService::Xdm,
+    ]
+}
+
+#[inline]
+fn hook_timeout() -> u64 {
+    10
+}
 
+#[inline]
+fn session_token_scope() -> String {
+    "openid".into()
+}
+
+#[inline]
+fn id_floor() -> u32 {
+    100_000
+}
+
+#[inline]
+fn subid_base() -> u32 {
+    // far above any uid Rauthy hands out and above the ranges `useradd` allocates by default
+    2_147_483_648
+}
+
+#[inline]
+fn subid_count() -> u32 {
+    65536
+}
+
+impl Config {
     pub fn load_create(pamh: &Pam) -> Result<Self, PamError> {
         match Self::read_create() {
             Ok(slf) => Ok(slf),
@@ -2,3 +2,13 @@ pub static ENV_SESSION: &str = "RAUTHY_PAM_SESSION";
 pub static ENV_USER_ID: &str = "RAUTHY_PAM_USER_ID";
 pub static ENV_USER_EMAIL: &str = "RAUTHY_PAM_USER_EMAIL";
 pub static ENV_USERNAME: &str = "RAUTHY_PAM_USERNAME";
+pub static ENV_TOKEN_SET: &str = "RAUTHY_PAM_TOKEN_SET";
+pub static ENV_ROLES: &str = "RAUTHY_PAM_ROLES";
+pub static ENV_GROUPS: &str = "RAUTHY_PAM_GROUPS";
+pub static ENV_UID: &str = "RAUTHY_PAM_UID";
+pub static ENV_GID: &str = "RAUTHY_PAM_GID";
+pub static ENV_HOOK: &str = "RAUTHY_PAM_HOOK";
+pub static ENV_SERVICE: &str = "RAUTHY_PAM_SERVICE";
+pub static ENV_SESSION_FIRST: &str = "RAUTHY_PAM_SESSION_FIRST";
+pub static ENV_SESSION_LAST: &str = "RAUTHY_PAM_SESSION_LAST";
+pub static ENV_SESSION_COUNT: &str = "RAUTHY_PAM_SESSION_COUNT";
@@ -0,0 +1,297 @@
+use crate::config::Config;
+use crate::pam::token::PamToken;
+use crate::pam::{conv, sys_err, sys_info};
+use chrono::{DateTime, Datelike, Local, NaiveTime};
+use pamsm::{Pam, PamError, PamLibExt};
+use serde::Deserialize;
+use std::fs;
+use std::net::IpAddr;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+static DAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+/// Local login policy, evaluated in `acct_mgmt` after Rauthy allowed the login.
+///
+/// Rules are checked in order and the first matching one wins, just like with `pam_access`.
+/// If no rule matches, `default` applies.
+#[derive(Debug, Deserialize)]
+pub struct AccessPolicy {
+    #[serde(default)]
+    default: AccessAction,
+    #[serde(default, rename = "rule")]
+    rules: Vec<AccessRule>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AccessAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// All given conditions must match. An empty condition matches anything.
+#[derive(Debug, Deserialize)]
+struct AccessRule {
+    action: AccessAction,
+    /// PAM service names like `sshd` or `login`
+    #[serde(default)]
+    services: Vec<String>,
+    /// matches, if the user has at least one of these Rauthy roles
+    #[serde(default)]
+    roles: Vec<String>,
+    /// matches, if the user is in at least one of these Rauthy groups
+    #[serde(default)]
+    groups: Vec<String>,
+    /// exact hosts or IPs, CIDRs like `10.0.0.0/8`, domain suffixes like `.example.com`,
+    /// or `LOCAL` for logins without a remote host
+    #[serde(default)]
+    rhosts: Vec<String>,
+    /// `pam_time` like specs in local time, e.g. `Mo-Fr 08:00-18:00` or `Wd`
+    #[serde(default)]
+    times: Vec<String>,
+    /// shown to the user on denial
+    message: Option<String>,
+}
+
+impl AccessPolicy {
+    /// Returns `SUCCESS`, if no policy is configured or the policy allows the login.
+    /// Any error while loading or evaluating the policy denies the login.
+    pub fn check(pamh: &Pam, config: &Config, token: &PamToken) -> PamError {
+        let Some(path) = &config.access_policy else {
+            return PamError::SUCCESS;
+        };
+
+        let res = Self::load(path).and_then(|slf| slf.evaluate(pamh, token));
+
+        match res {
+            Ok((AccessAction::Allow, _)) => PamError::SUCCESS,
+            Ok((AccessAction::Deny, message)) => {
+                conv::error(
+                    pamh,
+                    message
+                        .as_deref()
+                        .unwrap_or("Login denied by the local access policy"),
+                );
+                PamError::PERM_DENIED
+            }
+            Err(err) => {
+                sys_err(
+                    pamh,
+                    &format!("Access policy error in {path:?}, denying login: {err}"),
+                );
+                conv::error(pamh, "Login denied by the local access policy");
+                PamError::PERM_DENIED
+            }
+        }
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let meta = fs::metadata(path)?;
+        // the policy decides about logins, so it must be as protected as the config itself
+        if meta.uid() != 0 || meta.mode() & 0o022 != 0 {
+            return Err(anyhow::Error::msg(
+                "must be owned by root and not be writable by group or others",
+            ));
+        }
+
+        Ok(toml::from_str::<Self>(&fs::read_to_string(path)?)?)
+    }
+
+    fn evaluate(
+        &self,
+        pamh: &Pam,
+        token: &PamToken,
+    ) -> anyhow::Result<(AccessAction, Option<String>)> {
+        let service = pamh
+            .get_service()
+            .map_err(|err| anyhow::Error::msg(format!("Cannot read service: {err:?}")))?
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let rhost = pamh
+            .get_rhost()
+            .map_err(|err| anyhow::Error::msg(format!("Cannot read rhost: {err:?}")))?
+            .map(|s| s.to_string_lossy().to_string())
+            .filter(|s| !s.is_empty());
+        let now = Local::now();
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            if !rule.matches(token, &service, rhost.as_deref(), &now)? {
+                continue;
+            }
+
+            let msg = format!(
+                "Access policy rule #{} {:?} for user {} (service: {service}, rhost: {})",
+                i + 1,
+                rule.action,
+                token.username,
+                rhost.as_deref().unwrap_or("LOCAL"),
+            );
+            if rule.action == AccessAction::Deny {
+                sys_err(
+                    pamh,
+                    &format!(
+                        "{msg} - {}",
+                        rule.message.as_deref().unwrap_or("no message")
+                    ),
+                );
+            } else {
+                sys_info(pamh, &msg);
+            }
+            return Ok((rule.action, rule.message.clone()));
+        }
+
+        if self.default == AccessAction::Deny {
+            sys_err(
+                pamh,
+                &format!(
+                    "Access policy default Deny for user {} (service: {service}, rhost: {}) - \
+                    no rule matched",
+                    token.username,
+                    rhost.as_deref().unwrap_or("LOCAL"),
+                ),
+            );
+        }
+        Ok((self.default, None))
+    }
+}
+
+impl AccessRule {
+    fn matches(
+        &self,
+        token: &PamToken,
+        service: &str,
+        rhost: Option<&str>,
+        now: &DateTime<Local>,
+    ) -> anyhow::Result<bool> {
+        if !self.services.is_empty() && !self.services.iter().any(|s| s == service) {
+            return Ok(false);
+        }
+        if !self.roles.is_empty() && !token.roles.iter().any(|r| self.roles.contains(r)) {
+            return Ok(false);
+        }
+        if !self.groups.is_empty() && !token.groups.iter().any(|g| self.groups.contains(g)) {
+            return Ok(false);
+        }
+        if !self.rhosts.is_empty() && !self.rhosts.iter().any(|h| rhost_matches(h, rhost)) {
+            return Ok(false);
+        }
+        if !self.times.is_empty() {
+            let mut any = false;
+            for spec in &self.times {
+                // evaluate all of them to never silently ignore an invalid spec
+                any |= time_matches(spec, now)?;
+            }
+            if !any {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+fn rhost_matches(pattern: &str, rhost: Option<&str>) -> bool {
+    let Some(rhost) = rhost else {
+        return pattern == "LOCAL";
+    };
+
+    if pattern.starts_with('.') {
+        return rhost.ends_with(pattern);
+    }
+    if let Some((net, prefix)) = pattern.split_once('/') {
+        let (Ok(net), Ok(prefix), Ok(ip)) = (
+            net.parse::<IpAddr>(),
+            prefix.parse::<u32>(),
+            rhost.parse::<IpAddr>(),
+        ) else {
+            return false;
+        };
+
+        return match (net, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) if prefix <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) if prefix <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        };
+    }
+
+    pattern == rhost
+}
+
+/// Matches a spec like `Mo-Fr 08:00-18:00`, `Sa,Su`, `Wk 22:00-06:00` or `09:00-17:00`.
+/// `Wk` means weekdays, `Wd` the weekend and `Al` every day.
+fn time_matches(spec: &str, now: &DateTime<Local>) -> anyhow::Result<bool> {
+    let mut days_ok = true;
+    let mut hours_ok = true;
+
+    for part in spec.split_whitespace() {
+        if part.contains(':') {
+            hours_ok = hours_match(part, now.time())?;
+        } else {
+            days_ok = days_match(part, now.weekday().num_days_from_monday())?;
+        }
+    }
+
+    Ok(days_ok && hours_ok)
+}
+
+fn days_match(spec: &str, today: u32) -> anyhow::Result<bool> {
+    let day = |s: &str| {
+        DAYS.iter()
+            .position(|d| *d == s)
+            .map(|i| i as u32)
+            .ok_or_else(|| anyhow::Error::msg(format!("Invalid day in time spec: {s}")))
+    };
+
+    for item in spec.split(',') {
+        let matches = match item {
+            "Al" => true,
+            "Wk" => today < 5,
+            "Wd" => today >= 5,
+            _ => match item.split_once('-') {
+                Some((from, to)) => {
+                    let (from, to) = (day(from)?, day(to)?);
+                    if from <= to {
+                        (from..=to).contains(&today)
+                    } else {
+                        today >= from || today <= to
+                    }
+                }
+                None => day(item)? == today,
+            },
+        };
+        if matches {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// The end is exclusive. Ranges like `22:00-06:00` wrap around midnight.
+fn hours_match(spec: &str, now: NaiveTime) -> anyhow::Result<bool> {
+    let parse = |s: &str| {
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .map_err(|_| anyhow::Error::msg(format!("Invalid time in time spec: {s}")))
+    };
+
+    let Some((from, to)) = spec.split_once('-') else {
+        return Err(anyhow::Error::msg(format!(
+            "Invalid hours in time spec, expected HH:MM-HH:MM: {spec}"
+        )));
+    };
+    let (from, to) = (parse(from)?, parse(to)?);
+
+    if from <= to {
+        Ok(now >= from && now < to)
+    } else {
+        Ok(now >= from || now < to)
+    }
+}
@@ -4,14 +4,12 @@ use crate::api_types::{
 };
 use crate::config::Config;
 use crate::pam::token::PamToken;
-use crate::pam::webauthn::PamWebauthn;
-use crate::pam::{PamService, RauthyPam, sys_err, sys_info};
+use crate::pam::webauthn::{PASSKEY_DEADLINE, PamWebauthn};
+use crate::pam::{PamService, RauthyPam, conv, subid, sys_err, sys_info};
 use crate::{CLIENT, RT};
 use pamsm::{Pam, PamError, PamLibExt};
 use reqwest::Url;
-use std::ops::Deref;
-use std::time::Duration;
-use tokio::time;
+use tokio::time::Instant;
 
 impl RauthyPam {
     async fn preflight(
@@ -45,18 +43,12 @@ impl RauthyPam {
         }
     }
 
-    async fn mfa(pamh: &Pam, origin: Url, username: String) -> Result<String, String> {
-        println!("Provide your Passkey");
+    async fn mfa(pamh: &Pam, origin: Url, username: String) -> Result<String, PamError> {
+        conv::info(pamh, "Provide your Passkey");
 
-        // This short sleep will fight a race condition.
-        // The println may not appear if we try to find keys too quickly, when no key is inserted.
-        time::sleep(Duration::from_millis(100)).await;
-
-        let (ui, _) = crate::pam::webauthn::UI.deref();
+        let deadline = Instant::now() + PASSKEY_DEADLINE;
         let authenticator =
-            tokio::time::timeout(Duration::from_secs(20), PamWebauthn::wait_for_passkey(ui))
-                .await
-                .map_err(|_| "timed out".to_string())?;
+            PamWebauthn::wait_for_passkey(pamh, &crate::pam::webauthn::UI, deadline).await?;
 
         let url_start = format!("{origin}auth/v1/pam/mfa/start");
         let url_finish = format!("{origin}auth/v1/pam/mfa/finish");
@@ -66,49 +58,60 @@ impl RauthyPam {
             .json(&PamMfaStartRequest { username })
             .send()
             .await
-            .map_err(|err| err.to_string())?;
+            .map_err(|err| {
+                sys_err(pamh, &format!("Error starting MFA request: {err}"));
+                PamError::AUTHINFO_UNAVAIL
+            })?;
 
         let resp = if res.status().is_success() {
             res.json::<WebauthnAuthStartResponse>()
                 .await
-                .map_err(|err| err.to_string())?
+                .map_err(|err| {
+                    sys_err(pamh, &format!("Error extracting MFA start response: {err}"));
+                    PamError::AUTHINFO_UNAVAIL
+                })?
         } else {
             let err = res.text().await.unwrap_or_default();
-            eprintln!("{err}");
-            return Err(err);
+            conv::error(pamh, "Passkey authentication could not be started");
+            sys_err(pamh, &format!("MFA start request failed: {err}"));
+            return Err(PamError::AUTH_ERR);
         };
 
-        match PamWebauthn::perform_auth(pamh, authenticator, origin, resp.rcr.public_key).await {
-            Ok(pk_cred) => {
-                let res = CLIENT
-                    .post(url_finish)
-                    .json(&PamMfaFinishRequest {
-                        user_id: resp.user_id,
-                        data: WebauthnAuthFinishRequest {
-                            code: resp.code,
-                            data: pk_cred,
-                        },
-                    })
-                    .send()
-                    .await
-                    .map_err(|err| err.to_string())?;
-
-                let data = if res.status().is_success() {
-                    res.json::<WebauthnServiceReq>()
-                        .await
-                        .map_err(|err| err.to_string())?
-                } else {
-                    return Err(res.text().await.unwrap_or_default());
-                };
+        let pk_cred =
+            PamWebauthn::perform_auth(pamh, authenticator, origin, resp.rcr.public_key, deadline)
+                .await?;
 
-                return Ok(data.code);
-            }
-            Err(err) => {
-                sys_err(pamh, &format!("Passkey validation error: {err:?}"));
-                eprintln!("Passkey validation error: {err:?}");
-            }
+        let res = CLIENT
+            .post(url_finish)
+            .json(&PamMfaFinishRequest {
+                user_id: resp.user_id,
+                data: WebauthnAuthFinishRequest {
+                    code: resp.code,
+                    data: pk_cred,
+                },
+            })
+            .send()
+            .await
+            .map_err(|err| {
+                sys_err(pamh, &format!("Error finishing MFA request: {err}"));
+                PamError::AUTHINFO_UNAVAIL
+            })?;
+
+        if res.status().is_success() {
+            let data = res.json::<WebauthnServiceReq>().await.map_err(|err| {
+                sys_err(
+                    pamh,
+                    &format!("Error extracting MFA finish response: {err}"),
+                );
+                PamError::AUTHINFO_UNAVAIL
+            })?;
+            Ok(data.code)
+        } else {
+            let err = res.text().await.unwrap_or_default();
+            conv::error(pamh, "Passkey validation failed");
+            sys_err(pamh, &format!("Passkey validation error: {err}"));
+            Err(PamError::AUTH_ERR)
         }
-        Err("Passkey validation failed".to_string())
     }
 
     async fn send_login(origin: Url, payload: PamLoginRequest) -> Result<PamToken, String> {
@@ -132,7 +135,7 @@ impl RauthyPam {
         username: &str,
         svc: PamService,
         danger_auth_checked_locally: bool,
-    ) -> Result<(), PamError> {
+    ) -> Result<PamToken, PamError> {
         let config = Config::load_create(pamh)?;
 
         let preflight = match RT.block_on(Self::preflight(
@@ -182,8 +185,11 @@ impl RauthyPam {
                     login_req.webauthn_code = Some(webauthn_code);
                 }
                 Err(err) => {
-                    sys_err(pamh, &format!("Login Error: {err}"));
-                    return Err(PamError::AUTH_ERR);
+                    sys_err(
+                        pamh,
+                        &format!("Passkey Login Error for user {username}: {err}"),
+                    );
+                    return Err(err);
                 }
             }
         } else {
@@ -228,11 +234,17 @@ impl RauthyPam {
                     sys_err(pamh, &err.to_string());
                 }
 
-                if let Err(err) = token.save(pamh, &config) {
+                if config.subid_enable
+                    && let Err(err) = subid::ensure_allocated(&config, &token)
+                {
+                    sys_err(pamh, &format!("Error allocating subordinate ids: {err}"));
+                }
+
+                if let Err(err) = token.save(&config) {
                     sys_err(pamh, &format!("Error saving PAM token: {err}"));
                 }
 
-                Ok(())
+                Ok(token)
             }
             Err(err) => {
                 sys_err(pamh, &format!("Authentication Error: {err}"));
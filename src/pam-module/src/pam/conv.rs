@@ -0,0 +1,37 @@
+use crate::pam::sys_err;
+use pamsm::{Pam, PamError, PamLibExt, PamMsgStyle};
+
+// All user-facing output must go through the PAM conversation. Graphical greeters like
+// `gdm` or `sddm` do not have a terminal and anything written to stdout / stderr is lost.
+
+/// Shows an informational message to the user.
+pub fn info(pamh: &Pam, msg: &str) {
+    if let Err(err) = pamh.conv(Some(msg), PamMsgStyle::TEXT_INFO) {
+        sys_err(
+            pamh,
+            &format!("Cannot send info via PAM conversation: {err}"),
+        );
+    }
+}
+
+/// Shows an error message to the user.
+pub fn error(pamh: &Pam, msg: &str) {
+    if let Err(err) = pamh.conv(Some(msg), PamMsgStyle::ERROR_MSG) {
+        sys_err(
+            pamh,
+            &format!("Cannot send error via PAM conversation: {err}"),
+        );
+    }
+}
+
+/// Prompts the user for a secret value without echoing the input.
+///
+/// An `Err` means the conversation failed or was aborted by the user and the caller should
+/// give up instead of asking again.
+pub fn prompt_secret(pamh: &Pam, prompt: &str) -> Result<Option<String>, PamError> {
+    let resp = pamh.conv(Some(prompt), PamMsgStyle::PROMPT_ECHO_OFF)?;
+    Ok(resp
+        .and_then(|cstr| cstr.to_str().ok())
+        .filter(|s| !s.is_empty())
+        .map(String::from))
+}
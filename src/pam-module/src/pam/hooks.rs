@@ -0,0 +1,269 @@
+use crate::config::Config;
+use crate::constants::{
+    ENV_GID, ENV_GROUPS, ENV_HOOK, ENV_ROLES, ENV_SERVICE, ENV_SESSION_COUNT, ENV_SESSION_FIRST,
+    ENV_SESSION_LAST, ENV_UID, ENV_USER_EMAIL, ENV_USER_ID, ENV_USERNAME,
+};
+use crate::pam::PamService;
+use crate::pam::sessions::SessionState;
+use crate::pam::token::PamToken;
+use crate::pam::{sys_err, sys_info};
+use pamsm::Pam;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static PATH_ENV: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Runs the hook at `path` for the given `PamToken`. If `path` is a directory, all
+/// executables inside it are run in lexical order, like `run-parts` does. A failing hook
+/// is logged and does not stop the following ones.
+///
+/// Hooks are executed directly without any shell. User data is only ever passed as
+/// separate args and env vars, never as part of a command line.
+pub fn run(
+    pamh: &Pam,
+    config: &Config,
+    name: &str,
+    path: &Path,
+    token: &PamToken,
+    svc: &PamService,
+    session: Option<&SessionState>,
+) {
+    let hooks = match collect(path) {
+        Ok(hooks) => hooks,
+        Err(err) => {
+            sys_err(
+                pamh,
+                &format!("Cannot run {name} hooks from {path:?}: {err}"),
+            );
+            return;
+        }
+    };
+
+    let user = match &config.hook_user {
+        None => None,
+        Some(username) => match lookup_user(username) {
+            Ok(ids) => Some(ids),
+            Err(err) => {
+                // never fall back to root silently
+                sys_err(pamh, &format!("Cannot run {name} hooks: {err}"));
+                return;
+            }
+        },
+    };
+
+    let ctx = Context {
+        name,
+        token,
+        svc,
+        session,
+        user,
+    };
+    for hook in hooks {
+        if let Err(err) = exec(pamh, config, &ctx, &hook) {
+            sys_err(pamh, &format!("{name} hook {hook:?} failed: {err}"));
+        }
+    }
+}
+
+fn collect(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let meta = fs::metadata(path)?;
+    check_perms(path, &meta)?;
+
+    if meta.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    if !meta.is_dir() {
+        return Err(anyhow::Error::msg("neither a file nor a directory"));
+    }
+
+    let mut hooks = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // skip hidden files and package manager / editor leftovers
+        if name.starts_with('.')
+            || name.ends_with('~')
+            || name.contains(".rpm")
+            || name.contains(".dpkg")
+        {
+            continue;
+        }
+
+        let meta = fs::metadata(entry.path())?;
+        if meta.is_file() && meta.mode() & 0o111 != 0 {
+            hooks.push(entry.path());
+        }
+    }
+    hooks.sort();
+
+    Ok(hooks)
+}
+
+/// Hooks run as `root` or at least get started by it, so nobody else may be able to
+/// modify them.
+fn check_perms(path: &Path, meta: &fs::Metadata) -> anyhow::Result<()> {
+    if meta.uid() != 0 {
+        return Err(anyhow::Error::msg(format!(
+            "{path:?} must be owned by root"
+        )));
+    }
+    if meta.mode() & 0o022 != 0 {
+        return Err(anyhow::Error::msg(format!(
+            "{path:?} must not be writable by group or others, found {:#o}",
+            meta.mode() & 0o7777
+        )));
+    }
+    Ok(())
+}
+
+/// Everything that is the same for all hooks of a single run.
+struct Context<'a> {
+    name: &'a str,
+    token: &'a PamToken,
+    svc: &'a PamService,
+    session: Option<&'a SessionState>,
+    /// `(uid, gid)` to drop to
+    user: Option<(u32, u32)>,
+}
+
+fn exec(pamh: &Pam, config: &Config, ctx: &Context, path: &Path) -> anyhow::Result<()> {
+    let Context {
+        name,
+        token,
+        svc,
+        session,
+        user,
+    } = ctx;
+
+    // the dir has been checked already, but each file inside it must be safe as well
+    check_perms(path, &fs::metadata(path)?)?;
+
+    let mut cmd = Command::new(path);
+    // The positional args are only kept for backwards compatibility. Scripts should use
+    // the named env vars, which contain roles and groups as well.
+    cmd.arg(&token.username)
+        .arg(token.uid.to_string())
+        .arg(token.gid.to_string())
+        .arg(&token.user_id)
+        .arg(&token.user_email)
+        .env_clear()
+        .env("PATH", PATH_ENV)
+        .env(ENV_HOOK, name)
+        .env(ENV_SERVICE, svc.as_str())
+        .env(ENV_USERNAME, &token.username)
+        .env(ENV_UID, token.uid.to_string())
+        .env(ENV_GID, token.gid.to_string())
+        .env(ENV_USER_ID, &token.user_id)
+        .env(ENV_USER_EMAIL, &token.user_email)
+        .env(ENV_ROLES, token.roles.join(","))
+        .env(ENV_GROUPS, token.groups.join(","))
+        .current_dir("/")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // own process group, so we can kill everything the hook started on timeout
+        .process_group(0);
+    // missing, if session tracking failed, so hooks can decide what is safe to do
+    if let Some(session) = session {
+        cmd.env(ENV_SESSION_FIRST, session.first.to_string())
+            .env(ENV_SESSION_LAST, session.last.to_string())
+            .env(ENV_SESSION_COUNT, session.count.to_string());
+    }
+    if let Some((uid, gid)) = user {
+        cmd.uid(*uid).gid(*gid);
+    }
+
+    let mut child = cmd.spawn()?;
+    // read both pipes in the background, otherwise a chatty hook would block forever
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let status = wait_timeout(&mut child, Duration::from_secs(config.hook_timeout));
+
+    let stdout = output(stdout);
+    let stderr = output(stderr);
+    for line in stdout.lines().filter(|l| !l.is_empty()) {
+        sys_info(pamh, &format!("{name} hook {}: {line}", path.display()));
+    }
+    for line in stderr.lines().filter(|l| !l.is_empty()) {
+        sys_err(pamh, &format!("{name} hook {}: {line}", path.display()));
+    }
+
+    match status? {
+        Some(status) if status.success() => Ok(()),
+        Some(status) => Err(anyhow::Error::msg(format!("exited with {status}"))),
+        None => Err(anyhow::Error::msg(format!(
+            "timed out after {}s and has been killed",
+            config.hook_timeout
+        ))),
+    }
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        String::from_utf8_lossy(&buf).to_string()
+    })
+}
+
+/// Background processes started by a hook may inherit its pipes and keep them open. We
+/// only wait a short moment for them and never block the login because of it.
+fn output(handle: thread::JoinHandle<String>) -> String {
+    let start = Instant::now();
+    while !handle.is_finished() {
+        if start.elapsed() >= Duration::from_secs(1) {
+            return String::new();
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    handle.join().unwrap_or_default()
+}
+
+/// Returns `None` if the hook has been killed after `timeout`.
+fn wait_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> anyhow::Result<Option<std::process::ExitStatus>> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            // SAFETY: plain integer arguments only, the pgid is the pid of our own child
+            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Resolves a local user from `/etc/passwd` into `(uid, gid)`.
+fn lookup_user(username: &str) -> anyhow::Result<(u32, u32)> {
+    for line in fs::read_to_string("/etc/passwd")?.lines() {
+        let mut parts = line.split(':');
+        if let (Some(name), Some(_x), Some(Ok(uid)), Some(Ok(gid))) = (
+            parts.next(),
+            parts.next(),
+            parts.next().map(str::parse::<u32>),
+            parts.next().map(str::parse::<u32>),
+        ) && name == username
+        {
+            return Ok((uid, gid));
+        }
+    }
+
+    Err(anyhow::Error::msg(format!(
+        "hook_user {username} does not exist in /etc/passwd"
+    )))
+}
@@ -1,14 +1,29 @@
 use crate::config::Config;
-use crate::constants::{ENV_SESSION, ENV_USER_EMAIL, ENV_USER_ID, ENV_USERNAME};
+use crate::constants::{
+    ENV_GROUPS, ENV_ROLES, ENV_SESSION, ENV_TOKEN_SET, ENV_USER_EMAIL, ENV_USER_ID, ENV_USERNAME,
+};
+use crate::pam::access::AccessPolicy;
+use crate::pam::session_info::SessionInfo;
+use crate::pam::session_token::SessionTokenSet;
+use crate::pam::sessions::SessionState;
 use crate::pam::token::PamToken;
 use pamsm::{LogLvl, Pam, PamError, PamFlags, PamLibExt, PamServiceModule};
-use std::path::PathBuf;
-use std::process::Command;
+use serde::Deserialize;
 use std::sync::OnceLock;
 use std::{env, fs};
 
+mod access;
 mod auth;
+mod conv;
+mod hooks;
+mod runtime_dir;
+mod session_info;
+mod session_token;
+mod session_watch;
+mod sessions;
+mod subid;
 pub mod token;
+mod token_store;
 mod webauthn;
 
 static DEBUG: OnceLock<bool> = OnceLock::new();
@@ -52,7 +67,7 @@ macro_rules! get_nonlocal_username {
             return PamError::CRED_UNAVAIL;
         };
 
-        if RauthyPam::is_local_user(username) {
+        if RauthyPam::is_local_user($pamh, username) {
             return PamError::CRED_UNAVAIL;
         }
 
@@ -73,7 +88,7 @@ macro_rules! get_nonlocal_r_username {
             return PamError::CRED_UNAVAIL;
         };
 
-        if RauthyPam::is_local_user(username) {
+        if RauthyPam::is_local_user($pamh, username) {
             return PamError::CRED_UNAVAIL;
         }
 
@@ -81,7 +96,8 @@ macro_rules! get_nonlocal_r_username {
     }};
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(from = "String")]
 pub enum PamService {
     Gdm,
     Login,
@@ -95,73 +111,87 @@ pub enum PamService {
     Unknown,
 }
 
-pub struct RauthyPam;
-
-impl RauthyPam {
-    fn exec_script(pamh: &Pam, path: &PathBuf, token: &PamToken) -> anyhow::Result<()> {
-        let file = fs::File::open(path)?;
-        let meta = file.metadata()?;
-        if !meta.is_file() {
-            return Err(anyhow::Error::msg("target path is not a file"));
+impl From<String> for PamService {
+    fn from(svc: String) -> Self {
+        match svc.to_lowercase().as_str() {
+            "gdm" => Self::Gdm,
+            "login" => Self::Login,
+            "sddm" => Self::Sddm,
+            "sshd" => Self::Ssh,
+            "sudo" | "sudo-i" => Self::Sudo,
+            "su" | "su-l" => Self::Su,
+            "systemd-user" => Self::SystemdUser,
+            "xdm" => Self::Xdm,
+            s => Self::Other(s.to_string()),
         }
+    }
+}
 
-        #[cfg(unix)]
-        {
-            use std::os::unix::prelude::*;
+impl PamService {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Gdm => "gdm",
+            Self::Login => "login",
+            Self::Sddm => "sddm",
+            Self::Ssh => "sshd",
+            Self::Sudo => "sudo",
+            Self::Su => "su",
+            Self::SystemdUser => "systemd-user",
+            Self::Xdm => "xdm",
+            Self::Other(s) => s,
+            Self::Unknown => "unknown",
+        }
+    }
+}
 
-            if meta.uid() != 0 {
-                return Err(anyhow::Error::msg(format!(
-                    "{path:?} must be owned by root"
-                )));
-            }
+pub struct RauthyPam;
 
-            // we never want to execute a script as `root`, when it does not have
-            // the correct permissions
-            let mode = meta.permissions().mode();
-            if mode != 0o100700 {
-                return Err(anyhow::Error::msg(format!(
-                    "Invalid permissions on script, expected {:#o}, found {:#o}",
-                    0o100700, mode
-                )));
-            }
+impl RauthyPam {
+    /// Only sessions of services, for which at least one hook is enabled, are tracked.
+    /// Otherwise e.g. a `sudo` in between would make the last real logout look like it
+    /// was not the last one.
+    fn track_session(
+        pamh: &Pam,
+        config: &Config,
+        svc: &PamService,
+        username: &str,
+        open: bool,
+    ) -> Option<SessionState> {
+        if !config.exec_session_open_services.contains(svc)
+            && !config.exec_session_close_services.contains(svc)
+        {
+            return None;
         }
 
-        let cmd = format!(
-            "{} {} {} {} {} {}",
-            path.to_str().unwrap_or_default(),
-            token.username,
-            token.uid,
-            token.gid,
-            token.user_id,
-            token.user_email
-        );
-        let res = Command::new("/bin/bash").arg("-c").arg(cmd).output()?;
-
-        if res.status.success() {
-            if *DEBUG.get().unwrap() {
-                let out = String::from_utf8_lossy(res.stdout.as_slice());
-                if !out.is_empty() {
-                    sys_info(pamh, out.as_ref());
-                }
-            }
-            Ok(())
+        let res = if open {
+            sessions::open(username)
         } else {
-            let err = String::from_utf8_lossy(res.stderr.as_slice());
-            sys_err(pamh, err.as_ref());
-            Err(anyhow::Error::msg(err.to_string()))
+            sessions::close(username)
+        };
+        match res {
+            Ok(state) => Some(state),
+            Err(err) => {
+                sys_err(
+                    pamh,
+                    &format!("Cannot track sessions for {username}: {err}"),
+                );
+                None
+            }
         }
     }
 
     #[inline]
-    fn is_local_user(username: &str) -> bool {
-        let passwd = fs::read_to_string("/etc/passwd").expect("Cannot access /etc/passwd");
-        let name = format!("{username}:");
-        for line in passwd.lines() {
-            if line.starts_with(&name) {
-                return true;
+    fn is_local_user(pamh: &Pam, username: &str) -> bool {
+        match fs::read_to_string("/etc/passwd") {
+            Ok(passwd) => passwd
+                .lines()
+                .any(|line| line.split(':').next() == Some(username)),
+            Err(err) => {
+                // fail closed - we must never shadow a local account
+                sys_err(pamh, &format!("Cannot read /etc/passwd: {err}"));
+                true
             }
         }
-        false
     }
 
     #[inline]
@@ -179,17 +209,7 @@ impl RauthyPam {
                     sys_info(pamh, &format!("Service detected: {svc}"));
                 }
 
-                match svc.to_lowercase().as_str() {
-                    "gdm" => PamService::Gdm,
-                    "login" => PamService::Login,
-                    "sddm" => PamService::Sddm,
-                    "sshd" => PamService::Ssh,
-                    "sudo" | "sudo-i" => PamService::Sudo,
-                    "su" | "su-l" => PamService::Su,
-                    "systemd-user" => PamService::SystemdUser,
-                    "xdm" => PamService::Xdm,
-                    s => PamService::Other(s.to_string()),
-                }
+                PamService::from(svc.to_string())
             }
             Err(err) => {
                 sys_err(pamh, &format!("Cannot read service: {err:?}"));
@@ -215,7 +235,7 @@ impl PamServiceModule for RauthyPam {
         if let Some(token) = token
             && token.validate(config).is_ok()
         {
-            return PamError::SUCCESS;
+            return AccessPolicy::check(&pamh, config, token);
         }
 
         if svc == PamService::Ssh {
@@ -225,7 +245,7 @@ impl PamServiceModule for RauthyPam {
             // the user validity at this point instead, but only if the service is ssh.
 
             match Self::handle_authenticate(&pamh, username, svc, true) {
-                Ok(_) => PamError::SUCCESS,
+                Ok(token) => AccessPolicy::check(&pamh, config, &token),
                 Err(err) => {
                     sys_err(
                         &pamh,
@@ -249,8 +269,6 @@ impl PamServiceModule for RauthyPam {
         } else {
             get_nonlocal_username!(&pamh)
         };
-        eprintln!("authenticate username: {username} with svc {svc:?}");
-        // println!("authenticate username: {username}");
 
         match Self::handle_authenticate(&pamh, username, svc, false) {
             Ok(_) => PamError::SUCCESS,
@@ -288,6 +306,14 @@ impl PamServiceModule for RauthyPam {
         let (config, token) = load_config_token!(&pamh, username, false);
 
         if let Some(token) = token {
+            // `authenticate` is skipped for public key logins via ssh, and a still valid
+            // token skips the login in `acct_mgmt` as well.
+            if config.subid_enable
+                && let Err(err) = subid::ensure_allocated(config, token)
+            {
+                sys_err(&pamh, &format!("Error allocating subordinate ids: {err}"));
+            }
+
             let session_typ = if Self::get_service(&pamh) == PamService::Ssh {
                 "remote"
             } else {
@@ -306,17 +332,52 @@ impl PamServiceModule for RauthyPam {
             if let Err(err) = pamh.putenv(&format!("{ENV_USERNAME}={}", token.username)) {
                 sys_err(&pamh, &format!("Error setting ENV var: {err}"));
             }
+            // also readable by other modules further down the stack via `pam_getenv()`
+            if let Err(err) = pamh.putenv(&format!("{ENV_ROLES}={}", token.roles.join(","))) {
+                sys_err(&pamh, &format!("Error setting ENV var: {err}"));
+            }
+            if let Err(err) = pamh.putenv(&format!("{ENV_GROUPS}={}", token.groups.join(","))) {
+                sys_err(&pamh, &format!("Error setting ENV var: {err}"));
+            }
+            if let Err(err) = SessionInfo::save(token, session_typ) {
+                sys_err(&pamh, &format!("Cannot save session info: {err}"));
+            }
 
-            if let Some(path) = &config.exec_session_open {
-                let svc = Self::get_service(&pamh);
-
-                if (svc == PamService::Login || svc == PamService::Ssh)
-                    && let Err(err) = Self::exec_script(&pamh, path, token)
-                {
-                    sys_err(&pamh, &err.to_string());
+            if let Some(client_id) = &config.session_token_client_id {
+                match SessionTokenSet::fetch_save(config, token, client_id) {
+                    Ok(path) => {
+                        if let Err(err) =
+                            pamh.putenv(&format!("{ENV_TOKEN_SET}={}", path.display()))
+                        {
+                            sys_err(&pamh, &format!("Error setting ENV var: {err}"));
+                        }
+                    }
+                    Err(err) => {
+                        sys_err(&pamh, &format!("Cannot provide session token set: {err}"));
+                    }
                 }
             }
 
+            if let Err(err) = session_watch::open(token) {
+                sys_err(&pamh, &format!("Cannot register session: {err}"));
+            }
+
+            let svc = Self::get_service(&pamh);
+            let state = Self::track_session(&pamh, config, &svc, username, true);
+            if let Some(path) = &config.exec_session_open
+                && config.exec_session_open_services.contains(&svc)
+            {
+                hooks::run(
+                    &pamh,
+                    config,
+                    "session_open",
+                    path,
+                    token,
+                    &svc,
+                    state.as_ref(),
+                );
+            }
+
             PamError::SUCCESS
         } else {
             sys_err(&pamh, "No token in open session");
@@ -337,7 +398,16 @@ impl PamServiceModule for RauthyPam {
         // TODO delete token ? Or maybe full logout on server as well?
         // sys_info(&pamh, "in RauthyPam close_session");
 
-        if let Some(path) = &config.exec_session_close {
+        if let Err(err) = session_watch::close() {
+            sys_err(&pamh, &format!("Cannot unregister session: {err}"));
+        }
+
+        let svc = Self::get_service(&pamh);
+        let state = Self::track_session(&pamh, config, &svc, username, false);
+
+        if let Some(path) = &config.exec_session_close
+            && config.exec_session_close_services.contains(&svc)
+        {
             match token {
                 None => {
                     sys_err(
@@ -346,12 +416,15 @@ impl PamServiceModule for RauthyPam {
                     );
                 }
                 Some(token) => {
-                    let svc = Self::get_service(&pamh);
-                    if (svc == PamService::Login || svc == PamService::Ssh)
-                        && let Err(err) = Self::exec_script(&pamh, path, token)
-                    {
-                        sys_err(&pamh, &err.to_string());
-                    }
+                    hooks::run(
+                        &pamh,
+                        config,
+                        "session_close",
+                        path,
+                        token,
+                        &svc,
+                        state.as_ref(),
+                    );
                 }
             }
         }
@@ -385,7 +458,6 @@ fn debug(pamh: &Pam, module: &str) {
             "{module} : username {s} / service: {svc:?} / rhost: {rhost_s} / ruser: {ruser_s}"
         ),
     );
-    println!("{module} : username {s} / service: {svc:?} / rhost: {rhost_s} / ruser: {ruser_s}");
 }
 
 #[inline]
@@ -0,0 +1,111 @@
+use crate::pam::token::PamToken;
+use std::ffi::CString;
+use std::fs::{File, Permissions};
+use std::io::{self, ErrorKind, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt, fchown};
+use std::path::{Path, PathBuf};
+
+/// Atomically writes `content` into `/run/user/<uid>/rauthy/<name>`, readable only by the
+/// user. Returns the path to the written file.
+///
+/// We run as `root` inside a directory owned by the user, who could replace anything in
+/// there with a symlink at any time. Everything is therefore resolved relative to dir fds
+/// with `O_NOFOLLOW`, and ownership and permissions are only ever changed via these fds.
+pub fn write_user_file(token: &PamToken, name: &str, content: &[u8]) -> anyhow::Result<PathBuf> {
+    // `pam_systemd` runs before us in the session stack and creates the runtime dir.
+    // If it does not exist, we are most probably on a system without `systemd-logind`.
+    let runtime_dir = PathBuf::from(format!("/run/user/{}", token.uid));
+    let runtime = match open_dir(&runtime_dir) {
+        Ok(f) => f,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Err(anyhow::Error::msg(format!(
+                "{runtime_dir:?} does not exist - cannot save {name}"
+            )));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if runtime.metadata()?.uid() != token.uid {
+        return Err(anyhow::Error::msg(format!(
+            "{runtime_dir:?} is not owned by uid {}",
+            token.uid
+        )));
+    }
+
+    let dir_name = CString::new("rauthy")?;
+    // SAFETY: valid dir fd and NUL terminated path
+    let created = unsafe { libc::mkdirat(runtime.as_raw_fd(), dir_name.as_ptr(), 0o700) } == 0;
+    if !created {
+        let err = io::Error::last_os_error();
+        if err.kind() != ErrorKind::AlreadyExists {
+            return Err(err.into());
+        }
+    }
+    let dir = open_at(&runtime, &dir_name, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+    let meta = dir.metadata()?;
+    if meta.uid() == 0 && created && meta.nlink() == 2 {
+        // the empty dir we just created
+        fchown(&dir, Some(token.uid), Some(token.gid))?;
+    } else if meta.uid() != token.uid {
+        return Err(anyhow::Error::msg(format!(
+            "{runtime_dir:?}/rauthy is not owned by uid {}",
+            token.uid
+        )));
+    }
+    dir.set_permissions(Permissions::from_mode(0o700))?;
+
+    let file_name = CString::new(name)?;
+    let tmp_name = CString::new(format!("{name}.tmp"))?;
+    // SAFETY: valid dir fd and NUL terminated path, `unlinkat` never follows symlinks
+    unsafe { libc::unlinkat(dir.as_raw_fd(), tmp_name.as_ptr(), 0) };
+
+    let mut file = open_at(
+        &dir,
+        &tmp_name,
+        libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+        0o600,
+    )?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fchown(&file, Some(token.uid), Some(token.gid))?;
+
+    // SAFETY: valid dir fds and NUL terminated paths, `renameat` never follows symlinks
+    let res = unsafe {
+        libc::renameat(
+            dir.as_raw_fd(),
+            tmp_name.as_ptr(),
+            dir.as_raw_fd(),
+            file_name.as_ptr(),
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(runtime_dir.join("rauthy").join(name))
+}
+
+fn open_dir(path: &Path) -> io::Result<File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        .open(path)
+}
+
+/// `openat()` which never follows a symlink in the last component.
+fn open_at(dir: &File, name: &CString, flags: libc::c_int, mode: libc::c_uint) -> io::Result<File> {
+    // SAFETY: valid dir fd and NUL terminated path
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            mode,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: we own the fresh fd
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
@@ -0,0 +1,38 @@
+use crate::pam::runtime_dir;
+use crate::pam::token::PamToken;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Identity and session details of the logged-in user, written to the users' runtime dir,
+/// where it can be picked up by `rauthy-whoami`. The format must match the one in
+/// `rauthy-whoami`.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo<'a> {
+    pub user_id: &'a str,
+    pub email: &'a str,
+    pub username: &'a str,
+    pub uid: u32,
+    pub gid: u32,
+    pub roles: &'a [String],
+    pub groups: &'a [String],
+    pub token_exp: i64,
+    pub session_typ: &'a str,
+}
+
+impl<'a> SessionInfo<'a> {
+    /// Saves the session info into `/run/user/<uid>/rauthy/session`.
+    pub fn save(token: &'a PamToken, session_typ: &'a str) -> anyhow::Result<PathBuf> {
+        let slf = Self {
+            user_id: &token.user_id,
+            email: &token.user_email,
+            username: &token.username,
+            uid: token.uid,
+            gid: token.gid,
+            roles: &token.roles,
+            groups: &token.groups,
+            token_exp: token.exp,
+            session_typ,
+        };
+        runtime_dir::write_user_file(token, "session", toml::to_string(&slf)?.as_bytes())
+    }
+}
@@ -0,0 +1,71 @@
+use crate::api_types::{OidcTokenSet, PamOidcTokenRequest};
+use crate::config::Config;
+use crate::pam::runtime_dir;
+use crate::pam::token::PamToken;
+use crate::{CLIENT, RT};
+use chrono::Utc;
+use reqwest::header::AUTHORIZATION;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The OIDC token set for a user session, written to the users' runtime dir, where it can be
+/// picked up by `rauthy-token`. The format must match the one in `rauthy-token`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionTokenSet {
+    pub token_endpoint: String,
+    pub client_id: String,
+    pub access_token: String,
+    pub access_token_exp: i64,
+    pub id_token: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+impl SessionTokenSet {
+    /// Fetches a fresh token set from Rauthy for the configured client and saves it into
+    /// `/run/user/<uid>/rauthy/token_set`. Returns the path to the saved file.
+    pub fn fetch_save(
+        config: &Config,
+        token: &PamToken,
+        client_id: &str,
+    ) -> anyhow::Result<PathBuf> {
+        let ts = Self::fetch(config, token, client_id)?;
+        runtime_dir::write_user_file(token, "token_set", toml::to_string(&ts)?.as_bytes())
+    }
+
+    fn fetch(config: &Config, token: &PamToken, client_id: &str) -> anyhow::Result<Self> {
+        let url = format!("{}auth/v1/pam/oidc/token", config.rauthy_url);
+        let payload = PamOidcTokenRequest {
+            host_id: &config.host_id,
+            host_secret: &config.host_secret,
+            client_id,
+            scope: &config.session_token_scope,
+        };
+
+        let ts = RT.block_on(async {
+            let res = CLIENT
+                .post(url)
+                .header(AUTHORIZATION, format!("PamToken {}", token.id))
+                .json(&payload)
+                .send()
+                .await?;
+
+            if res.status().is_success() {
+                Ok(res.json::<OidcTokenSet>().await?)
+            } else {
+                let err = res.text().await.unwrap_or_default();
+                Err(anyhow::Error::msg(format!(
+                    "Error fetching session token set: {err}"
+                )))
+            }
+        })?;
+
+        Ok(Self {
+            token_endpoint: format!("{}auth/v1/oidc/token", config.rauthy_url),
+            client_id: client_id.to_string(),
+            access_token: ts.access_token,
+            access_token_exp: Utc::now().timestamp() + ts.expires_in,
+            id_token: ts.id_token,
+            refresh_token: ts.refresh_token,
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use crate::pam::token::PamToken;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+#[cfg(debug_assertions)]
+static PROXY_SOCKET: &str = "/tmp/rauthy/rauthy_proxy.sock";
+#[cfg(not(debug_assertions))]
+static PROXY_SOCKET: &str = "/run/rauthy/rauthy_proxy.sock";
+
+/// Registers the session with `rauthy-nss`, which re-validates the `PamToken` periodically
+/// and terminates all sessions of the user, once it has been disabled in Rauthy. The format
+/// must match the one in `rauthy-nss`.
+#[derive(Debug, Serialize)]
+struct SessionOpenRequest<'a> {
+    user_id: &'a str,
+    username: &'a str,
+    uid: u32,
+    token_id: &'a str,
+    token_exp: i64,
+}
+
+/// The proxy identifies the session by our PID, which is the same for `close_session`.
+pub fn open(token: &PamToken) -> anyhow::Result<()> {
+    let body = toml::to_string(&SessionOpenRequest {
+        user_id: &token.user_id,
+        username: &token.username,
+        uid: token.uid,
+        token_id: &token.id,
+        token_exp: token.exp,
+    })?;
+    post("/sessions/open", &body)
+}
+
+pub fn close() -> anyhow::Result<()> {
+    post("/sessions/close", "")
+}
+
+/// A raw HTTP/1.0 request, just like `rauthy-whoami` does it. The proxy answers quickly or
+/// not at all, and the session must never hang because of it.
+fn post(path: &str, body: &str) -> anyhow::Result<()> {
+    let mut stream = UnixStream::connect(PROXY_SOCKET)
+        .map_err(|err| anyhow::Error::msg(format!("Cannot connect to {PROXY_SOCKET}: {err}")))?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+    stream.write_all(
+        format!(
+            "POST {path} HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/toml\r\n\
+            Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .as_bytes(),
+    )?;
+
+    let mut resp = String::with_capacity(128);
+    stream.read_to_string(&mut resp)?;
+    let (head, body) = resp.split_once("\r\n\r\n").unwrap_or((&resp, ""));
+    if head.lines().next().unwrap_or_default().contains(" 200 ") {
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg(format!(
+            "rauthy-nss rejected {path}: {}",
+            body.trim()
+        )))
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::Permissions;
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::{fs, io, process};
+
+static SESSIONS_PATH: &str = "/run/rauthy/sessions";
+
+/// Tells session hooks whether they run for the first session of a user or after the last
+/// one has been closed, so e.g. shares are mounted only once and unmounted at the very end.
+#[derive(Debug)]
+pub struct SessionState {
+    pub first: bool,
+    pub last: bool,
+    /// open sessions after this change
+    pub count: usize,
+}
+
+/// Registers the current session for `username`.
+///
+/// Sessions are tracked by the PID of the process holding the PAM handle, which is the
+/// same one for open and close. Together with the process start time, entries of crashed
+/// or killed sessions can be detected reliably and are pruned, even if the PID has been
+/// reused in the meantime.
+pub fn open(username: &str) -> anyhow::Result<SessionState> {
+    let dir = user_dir(username)?;
+    let _lock = Lock::acquire(&dir)?;
+
+    let before = prune(&dir)?;
+    let pid = process::id();
+    let start = start_time(pid)?;
+    fs::write(dir.join(pid.to_string()), start.to_string())?;
+
+    let count = prune(&dir)?;
+    Ok(SessionState {
+        first: before == 0,
+        last: false,
+        count,
+    })
+}
+
+/// Removes the current session for `username`.
+pub fn close(username: &str) -> anyhow::Result<SessionState> {
+    let dir = user_dir(username)?;
+    let _lock = Lock::acquire(&dir)?;
+
+    match fs::remove_file(dir.join(process::id().to_string())) {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let count = prune(&dir)?;
+    Ok(SessionState {
+        first: false,
+        last: count == 0,
+        count,
+    })
+}
+
+fn user_dir(username: &str) -> anyhow::Result<PathBuf> {
+    if username.contains('/') || username.starts_with('.') {
+        return Err(anyhow::Error::msg(format!(
+            "Invalid username for session tracking: {username}"
+        )));
+    }
+
+    let path = Path::new(SESSIONS_PATH).join(username);
+    fs::create_dir_all(&path)?;
+    fs::set_permissions(SESSIONS_PATH, Permissions::from_mode(0o700))?;
+    fs::set_permissions(&path, Permissions::from_mode(0o700))?;
+    Ok(path)
+}
+
+/// Removes all entries of dead sessions and returns the amount of remaining ones.
+fn prune(dir: &Path) -> anyhow::Result<usize> {
+    let mut count = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let alive = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+            .zip(
+                fs::read_to_string(entry.path())
+                    .ok()
+                    .and_then(|s| s.trim().parse::<u64>().ok()),
+            )
+            .is_some_and(|(pid, start)| start_time(pid).ok() == Some(start));
+
+        if alive {
+            count += 1;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Returns the start time of `pid` in clock ticks after boot from `/proc/<pid>/stat`.
+fn start_time(pid: u32) -> anyhow::Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    // The process name in field 2 may contain spaces and parens. Everything after the last
+    // `)` starts with field 3, which makes `starttime` (field 22) the 20th one.
+    stat.rsplit_once(')')
+        .and_then(|(_, rest)| rest.split_whitespace().nth(19))
+        .and_then(|s| s.parse::<u64>().ok())
+        .ok_or_else(|| anyhow::Error::msg(format!("Cannot parse /proc/{pid}/stat")))
+}
+
+/// Exclusive `flock()` on the user's session dir, released when the fd is closed on drop.
+struct Lock {
+    _file: fs::File,
+}
+
+impl Lock {
+    fn acquire(dir: &Path) -> anyhow::Result<Self> {
+        let file = fs::File::open(dir)?;
+        // SAFETY: the fd is valid for as long as `file` lives
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self { _file: file })
+    }
+}
@@ -0,0 +1,157 @@
+use crate::config::Config;
+use crate::pam::token::PamToken;
+use std::fs::Permissions;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fs, thread};
+
+static SUBUID_PATH: &str = "/etc/subuid";
+static SUBGID_PATH: &str = "/etc/subgid";
+
+/// Makes sure the user has subordinate uid / gid ranges, which are needed for rootless
+/// containers. Ranges are derived from the uid, so they are the same on every host and can
+/// never overlap with another Rauthy user:
+///
+/// `start = subid_base + (uid - id_floor) * subid_count`
+pub fn ensure_allocated(config: &Config, token: &PamToken) -> anyhow::Result<()> {
+    let start = range_start(config, token.uid)?;
+    for path in [SUBUID_PATH, SUBGID_PATH] {
+        allocate(Path::new(path), &token.username, start, config.subid_count)?;
+    }
+    Ok(())
+}
+
+fn range_start(config: &Config, uid: u32) -> anyhow::Result<u32> {
+    if config.subid_count == 0 {
+        return Err(anyhow::Error::msg("subid_count must not be 0"));
+    }
+    let offset = uid.checked_sub(config.id_floor).ok_or_else(|| {
+        anyhow::Error::msg(format!(
+            "uid {uid} is below id_floor {} - cannot allocate subordinate ids",
+            config.id_floor
+        ))
+    })?;
+
+    let start = config.subid_base as u64 + offset as u64 * config.subid_count as u64;
+    let end = start + config.subid_count as u64 - 1;
+    // `u32::MAX` is `(uid_t) -1` and must never be used
+    if end >= u32::MAX as u64 {
+        return Err(anyhow::Error::msg(format!(
+            "Subordinate id range for uid {uid} would overflow - decrease subid_base or subid_count"
+        )));
+    }
+
+    Ok(start as u32)
+}
+
+fn allocate(path: &Path, username: &str, start: u32, count: u32) -> anyhow::Result<()> {
+    let _lock = Lock::acquire(path)?;
+
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let end = start as u64 + count as u64 - 1;
+    for line in content.lines() {
+        let mut parts = line.split(':');
+        let (Some(name), Some(Ok(other_start)), Some(Ok(other_count))) = (
+            parts.next(),
+            parts.next().map(str::parse::<u64>),
+            parts.next().map(str::parse::<u64>),
+        ) else {
+            continue;
+        };
+
+        if name == username {
+            // already allocated, either by us or manually by an admin
+            return Ok(());
+        }
+        if other_count > 0 && other_start <= end && (start as u64) < other_start + other_count {
+            return Err(anyhow::Error::msg(format!(
+                "Subordinate id range {start}-{end} for {username} overlaps with the one for \
+                {name} in {path:?} - not allocating"
+            )));
+        }
+    }
+
+    let mut new = content;
+    if !new.is_empty() && !new.ends_with('\n') {
+        new.push('\n');
+    }
+    new.push_str(&format!("{username}:{start}:{count}\n"));
+
+    let path_tmp = PathBuf::from(format!("{}+", path.display()));
+    let mut file = fs::File::create(&path_tmp)?;
+    file.write_all(new.as_bytes())?;
+    file.sync_all()?;
+    fs::set_permissions(&path_tmp, Permissions::from_mode(0o644))?;
+    fs::rename(&path_tmp, path)?;
+
+    Ok(())
+}
+
+/// `/etc/sub[ug]id.lock` is the same lock file `shadow-utils` uses, so we never race with
+/// `usermod` or `newusers`. Just like `lckpwdf()`, the lock contains the PID of its owner and
+/// is created via a hard link, which makes it possible to detect and remove stale locks of
+/// processes which died while holding it.
+struct Lock(PathBuf);
+
+impl Lock {
+    fn acquire(path: &Path) -> anyhow::Result<Self> {
+        let lock = PathBuf::from(format!("{}.lock", path.display()));
+        let pid = std::process::id();
+
+        let lock_tmp = PathBuf::from(format!("{}.{pid}", path.display()));
+        let _ = fs::remove_file(&lock_tmp);
+        fs::write(&lock_tmp, pid.to_string())?;
+        let res = Self::link(&lock_tmp, &lock);
+        let _ = fs::remove_file(&lock_tmp);
+        res?;
+
+        Ok(Self(lock))
+    }
+
+    fn link(lock_tmp: &Path, lock: &Path) -> anyhow::Result<()> {
+        for _ in 0..20 {
+            match fs::hard_link(lock_tmp, lock) {
+                Ok(_) => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if Self::is_stale(lock) {
+                        let _ = fs::remove_file(lock);
+                    } else {
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Err(anyhow::Error::msg(format!("Cannot acquire lock {lock:?}")))
+    }
+
+    /// A lock is stale, if the process with the PID inside does not exist anymore. Locks
+    /// without a valid PID are never considered stale, same as with `shadow-utils`.
+    fn is_stale(lock: &Path) -> bool {
+        let Some(pid) = fs::read_to_string(lock)
+            .ok()
+            .and_then(|s| s.trim().parse::<libc::pid_t>().ok())
+            .filter(|pid| *pid > 0)
+        else {
+            return false;
+        };
+
+        // SAFETY: signal 0 only checks for the existence of the process
+        let res = unsafe { libc::kill(pid, 0) };
+        res != 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::config::Config;
+use crate::pam::{sys_err, sys_info, token_store};
 use crate::{CLIENT, RT, copy_dir};
 use chrono::Utc;
 use pamsm::Pam;
@@ -72,12 +73,31 @@ impl PamToken {
             };
         }
 
-        let base = config.data_path_user(pamh, username)?;
-        let path = base.join("token");
-
-        let bytes = fs::read(path)?;
+        let store = config.token_store.build(config);
+        let Some(sealed) = store.load(username)? else {
+            return Ok(None);
+        };
+        let bytes = match token_store::unseal(config, &sealed) {
+            Ok(b) => b,
+            Err(_) if Self::is_legacy(&sealed) => {
+                sys_info(
+                    pamh,
+                    &format!(
+                        "Discarding unauthenticated PamToken from a previous version for user \
+                        {username} - a fresh login is needed once"
+                    ),
+                );
+                store.delete(username)?;
+                return Ok(None);
+            }
+            Err(err) => {
+                sys_err(pamh, &format!("{err} - deleting it for user {username}"));
+                store.delete(username)?;
+                return Ok(None);
+            }
+        };
         let (slf, _) =
-            bincode::serde::decode_from_slice::<Self, _>(&bytes, bincode::config::standard())?;
+            bincode::serde::decode_from_slice::<Self, _>(bytes, bincode::config::standard())?;
 
         if with_validation {
             match slf.validate(config) {
@@ -86,7 +106,10 @@ impl PamToken {
                     Ok(Some(TOKEN.get().unwrap().as_ref().unwrap()))
                 }
                 Err(err) => {
-                    eprintln!("Token Validation Error: {err}");
+                    sys_info(
+                        pamh,
+                        &format!("PamToken for user {username} is not valid: {err}"),
+                    );
                     Ok(None)
                 }
             }
@@ -96,15 +119,21 @@ impl PamToken {
         }
     }
 
-    #[inline]
-    pub fn save(&self, pamh: &Pam, config: &Config) -> anyhow::Result<()> {
-        let base = config.data_path_user(pamh, &self.username)?;
-        let path = base.join("token");
+    /// Tokens saved before they were sealed are a plain bincode encoded `PamToken`. They are
+    /// never re-sealed, because we cannot tell if they have been modified in the meantime.
+    fn is_legacy(bytes: &[u8]) -> bool {
+        bincode::serde::decode_from_slice::<Self, _>(bytes, bincode::config::standard())
+            .is_ok_and(|(_, len)| len == bytes.len())
+    }
 
+    #[inline]
+    pub fn save(&self, config: &Config) -> anyhow::Result<()> {
         let bytes = bincode::serde::encode_to_vec(self, bincode::config::standard())?;
-        fs::write(path, bytes)?;
-
-        Ok(())
+        let sealed = token_store::seal(config, &bytes)?;
+        config
+            .token_store
+            .build(config)
+            .save(&self.username, &sealed, self.exp)
     }
 
     pub fn validate(&self, config: &Config) -> Result<(), String> {
@@ -0,0 +1,294 @@
+use crate::config::{Config, TokenStoreType};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use std::ffi::CString;
+use std::fs::Permissions;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::{fs, io, ptr};
+
+static TMPFS_PATH: &str = "/run/rauthy/pam";
+static KEY_FILE: &str = "token.key";
+const KEY_LEN: usize = 32;
+const MAC_LEN: usize = 32;
+
+/// Storage backend for serialized `PamToken`s.
+///
+/// Implementations only move opaque bytes around. Authentication of the content happens in
+/// `seal()` / `unseal()`, so every backend gets tamper detection for free.
+pub trait TokenStore {
+    fn load(&self, username: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// `exp` is the unix timestamp after which the data is useless and may be dropped.
+    fn save(&self, username: &str, data: &[u8], exp: i64) -> anyhow::Result<()>;
+
+    fn delete(&self, username: &str) -> anyhow::Result<()>;
+}
+
+impl TokenStoreType {
+    pub fn build(&self, config: &Config) -> Box<dyn TokenStore> {
+        match self {
+            Self::File => Box::new(FileStore {
+                base: config.data_path.clone(),
+            }),
+            Self::Tmpfs => Box::new(FileStore {
+                base: PathBuf::from(TMPFS_PATH),
+            }),
+            Self::Keyring => Box::new(KeyringStore),
+        }
+    }
+}
+
+/// Prepends an HMAC-SHA256 over `data` with the host-local key.
+pub fn seal(config: &Config, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut res = Vec::with_capacity(MAC_LEN + data.len());
+    res.extend(mac(config, data)?);
+    res.extend_from_slice(data);
+    Ok(res)
+}
+
+/// Verifies and strips the HMAC, which has been added with `seal()`.
+pub fn unseal<'a>(config: &Config, sealed: &'a [u8]) -> anyhow::Result<&'a [u8]> {
+    if sealed.len() < MAC_LEN {
+        return Err(anyhow::Error::msg("PAM token is too short to be valid"));
+    }
+
+    let (tag, data) = sealed.split_at(MAC_LEN);
+    if !openssl::memcmp::eq(tag, &mac(config, data)?) {
+        return Err(anyhow::Error::msg(
+            "PAM token authentication failed - it has been tampered with",
+        ));
+    }
+
+    Ok(data)
+}
+
+fn mac(config: &Config, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let key = PKey::hmac(&host_key(&config.data_path)?)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Reads the host-local MAC key or creates a new random one, if it does not exist yet.
+fn host_key(data_path: &Path) -> anyhow::Result<Vec<u8>> {
+    let path = data_path.join(KEY_FILE);
+
+    match fs::read(&path) {
+        Ok(key) if key.len() == KEY_LEN => return Ok(key),
+        Ok(_) => {
+            return Err(anyhow::Error::msg(format!(
+                "{path:?} is corrupted - remove it to generate a new one"
+            )));
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut key = vec![0; KEY_LEN];
+    openssl::rand::rand_bytes(&mut key)?;
+
+    fs::create_dir_all(data_path)?;
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+    {
+        Ok(mut file) => {
+            file.write_all(&key)?;
+            file.sync_all()?;
+            Ok(key)
+        }
+        // another login created it in the meantime
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(fs::read(&path)?),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Saves tokens in `<base>/<username>/token`. Used for both the persistent `data_path` and
+/// the tmpfs store in `/run/rauthy/pam`.
+struct FileStore {
+    base: PathBuf,
+}
+
+impl FileStore {
+    fn path_user(&self, username: &str) -> anyhow::Result<PathBuf> {
+        if username.contains('/') || username.starts_with('.') {
+            return Err(anyhow::Error::msg(format!(
+                "Invalid username for the token store: {username}"
+            )));
+        }
+
+        let path = self.base.join(username);
+        fs::create_dir_all(&path)?;
+        // `x` is needed on dirs, otherwise they are not traversable, even for root
+        // without `CAP_DAC_OVERRIDE`.
+        fs::set_permissions(&self.base, Permissions::from_mode(0o700))?;
+        fs::set_permissions(&path, Permissions::from_mode(0o700))?;
+
+        Ok(path)
+    }
+}
+
+impl TokenStore for FileStore {
+    fn load(&self, username: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let path = self.path_user(username)?.join("token");
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, username: &str, data: &[u8], _exp: i64) -> anyhow::Result<()> {
+        let base = self.path_user(username)?;
+        let path = base.join("token");
+        let path_tmp = base.join("token.tmp");
+        let _ = fs::remove_file(&path_tmp);
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path_tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(path_tmp, path)?;
+
+        Ok(())
+    }
+
+    fn delete(&self, username: &str) -> anyhow::Result<()> {
+        let path = self.path_user(username)?.join("token");
+        match fs::remove_file(path) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+// We only need a handful of `keyctl` operations, which is not worth an additional dependency.
+const KEY_SPEC_USER_KEYRING: libc::c_long = -4;
+const KEYCTL_SEARCH: libc::c_long = 10;
+const KEYCTL_READ: libc::c_long = 11;
+const KEYCTL_SET_TIMEOUT: libc::c_long = 15;
+const KEYCTL_INVALIDATE: libc::c_long = 21;
+
+/// Saves tokens as `user` keys in the kernel keyring of the calling uid, which is `root` for
+/// all PAM operations. Keys never touch the disk and the kernel drops them on expiry.
+struct KeyringStore;
+
+impl KeyringStore {
+    #[inline]
+    fn description(username: &str) -> anyhow::Result<CString> {
+        Ok(CString::new(format!("rauthy_pam:{username}"))?)
+    }
+
+    fn search(username: &str) -> anyhow::Result<Option<libc::c_long>> {
+        let desc = Self::description(username)?;
+
+        // SAFETY: all pointers are valid, NUL-terminated C strings for the duration of the call
+        let serial = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                KEYCTL_SEARCH,
+                KEY_SPEC_USER_KEYRING,
+                c"user".as_ptr(),
+                desc.as_ptr(),
+                0 as libc::c_long,
+            )
+        };
+
+        if serial >= 0 {
+            return Ok(Some(serial));
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENOKEY) | Some(libc::EKEYEXPIRED) | Some(libc::EKEYREVOKED) => Ok(None),
+            _ => Err(err.into()),
+        }
+    }
+}
+
+impl TokenStore for KeyringStore {
+    fn load(&self, username: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(serial) = Self::search(username)? else {
+            return Ok(None);
+        };
+
+        let mut buf: Vec<u8> = Vec::new();
+        loop {
+            // SAFETY: the kernel writes at most `buf.capacity()` bytes into `buf`
+            let len = unsafe {
+                libc::syscall(
+                    libc::SYS_keyctl,
+                    KEYCTL_READ,
+                    serial,
+                    if buf.capacity() == 0 {
+                        ptr::null_mut()
+                    } else {
+                        buf.as_mut_ptr()
+                    },
+                    buf.capacity(),
+                )
+            };
+            if len < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            let len = len as usize;
+            if len <= buf.capacity() && buf.capacity() > 0 {
+                // SAFETY: the kernel initialized exactly `len` bytes
+                unsafe { buf.set_len(len) };
+                return Ok(Some(buf));
+            }
+            // first call or the key has been updated in between -> retry with the new size
+            buf = Vec::with_capacity(len.max(1));
+        }
+    }
+
+    fn save(&self, username: &str, data: &[u8], exp: i64) -> anyhow::Result<()> {
+        let desc = Self::description(username)?;
+
+        // SAFETY: all pointers are valid for the given lengths for the duration of the call
+        let serial = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                c"user".as_ptr(),
+                desc.as_ptr(),
+                data.as_ptr(),
+                data.len(),
+                KEY_SPEC_USER_KEYRING,
+            )
+        };
+        if serial < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let timeout = (exp - chrono::Utc::now().timestamp()).max(1) as libc::c_uint;
+        // SAFETY: plain integer arguments only
+        let res = unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_SET_TIMEOUT, serial, timeout) };
+        if res < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, username: &str) -> anyhow::Result<()> {
+        let Some(serial) = Self::search(username)? else {
+            return Ok(());
+        };
+
+        // SAFETY: plain integer arguments only
+        let res = unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_INVALIDATE, serial) };
+        if res < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
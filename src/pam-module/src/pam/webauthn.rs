@@ -1,9 +1,13 @@
-use crate::pam::{sys_err, sys_info};
-use pamsm::{Pam, PamLibExt};
+use crate::pam::{conv, sys_err, sys_info};
+use pamsm::{Pam, PamError};
+use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
-use std::sync::LazyLock;
-use std::time::Instant;
-use tokio::task;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tokio::time;
+use tokio::time::Instant;
 use webauthn_authenticator_rs::AuthenticatorBackend;
 use webauthn_authenticator_rs::ctap2::CtapAuthenticator;
 use webauthn_authenticator_rs::prelude::Url;
@@ -14,11 +18,23 @@ use webauthn_authenticator_rs::usb::{USBToken, USBTransport};
 use webauthn_rs::prelude::PublicKeyCredential;
 use webauthn_rs_core::proto::PublicKeyCredentialRequestOptions;
 
-pub static UI: LazyLock<(PamWebauthn, flume::Receiver<PamReq>)> = LazyLock::new(|| {
-    let (tx, rx) = flume::bounded(1);
-    let ui = PamWebauthn { tx: tx.clone() };
-    (ui, rx)
-});
+/// Hard upper limit for the whole Passkey flow, no matter which timeout Rauthy requests.
+pub const PASSKEY_DEADLINE: Duration = Duration::from_secs(90);
+/// How long we wait for a Passkey to be inserted before giving up.
+const PASSKEY_INSERT_TIMEOUT: Duration = Duration::from_secs(20);
+/// Max amount of `perform_auth` attempts on the device, e.g. after a wrong PIN.
+const MAX_ATTEMPTS: u32 = 3;
+
+thread_local! {
+    /// Channel and deadline of the flow the current device thread belongs to. Each call to
+    /// `perform_auth` gets its own channel, so a device thread left over from an earlier,
+    /// aborted flow can never leak messages into a later one. The device thread must never
+    /// wait for a PIN beyond the deadline, because nobody would answer anymore.
+    static FLOW: RefCell<Option<(flume::Sender<PamReq>, std::time::Instant)>> =
+        const { RefCell::new(None) };
+}
+
+pub static UI: PamWebauthn = PamWebauthn;
 
 #[derive(Debug)]
 pub enum PamReq {
@@ -26,11 +42,10 @@ pub enum PamReq {
     Err(String),
     GetPin(flume::Sender<String>),
     Result(PublicKeyCredential),
+    Exhausted,
 }
 
-pub struct PamWebauthn {
-    tx: flume::Sender<PamReq>,
-}
+pub struct PamWebauthn;
 
 impl Debug for PamWebauthn {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -44,122 +59,196 @@ impl PamWebauthn {
         mut authenticator: CtapAuthenticator<'static, USBToken, Self>,
         origin: Url,
         public_key: PublicKeyCredentialRequestOptions,
-    ) -> anyhow::Result<PublicKeyCredential> {
-        let (ui, rx) = &*UI;
+        deadline: Instant,
+    ) -> Result<PublicKeyCredential, PamError> {
+        // Unbounded on purpose: the `UiCallback` must never block, and the device thread may
+        // still send messages after we stopped listening because of a timeout or cancellation.
+        let (tx, rx) = flume::unbounded();
+        let deadline_device = deadline.into_std();
 
-        task::spawn_blocking(move || {
-            let timeout = public_key.timeout.unwrap_or(60_000);
-            let start = Instant::now();
+        let abort = AbortOnDrop::default();
+        let abort_device = abort.0.clone();
 
-            loop {
-                match authenticator.perform_auth(
-                    origin.clone(),
-                    public_key.clone(),
-                    start.elapsed().as_millis() as u32 - timeout,
-                ) {
+        // The device communication is fully blocking and cannot be interrupted. We use a
+        // dedicated thread instead of `spawn_blocking`, so a hanging device can never starve
+        // the runtime. The thread will exit on its own once the `deadline` has been reached.
+        thread::spawn(move || {
+            FLOW.set(Some((tx.clone(), deadline_device)));
+
+            let start = std::time::Instant::now();
+            let timeout = public_key
+                .timeout
+                .unwrap_or(60_000)
+                .min(millis(deadline_device.saturating_duration_since(start)));
+
+            for attempt in 1..=MAX_ATTEMPTS {
+                let remaining = timeout.saturating_sub(millis(start.elapsed()));
+                if remaining == 0 || abort_device.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                match authenticator.perform_auth(origin.clone(), public_key.clone(), remaining) {
                     Ok(pk_cred) => {
-                        ui.tx.send(PamReq::Result(pk_cred)).unwrap();
-                        break;
+                        let _ = tx.send(PamReq::Result(pk_cred));
+                        return;
                     }
                     Err(err) => {
-                        ui.tx
-                            .send(PamReq::Err(format!("Passkey validation error: {err:?}")))
-                            .unwrap();
+                        let _ = tx.send(PamReq::Err(format!(
+                            "Passkey validation error (attempt {attempt}/{MAX_ATTEMPTS}): {err:?}"
+                        )));
                     }
                 }
             }
+
+            let _ = tx.send(PamReq::Exhausted);
         });
 
-        let mut touch_requested = false;
-        loop {
-            match rx.recv_async().await.unwrap() {
-                PamReq::Info(msg) => {
-                    if msg.contains("Touch") {
-                        if !touch_requested {
-                            println!("{msg}");
-                            touch_requested = true;
+        with_deadline(pamh, deadline, async {
+            let mut touch_requested = false;
+            loop {
+                let req = rx.recv_async().await.map_err(|err| {
+                    sys_err(pamh, &format!("Passkey channel closed: {err}"));
+                    PamError::SERVICE_ERR
+                })?;
+
+                match req {
+                    PamReq::Info(msg) => {
+                        if msg.contains("Touch") {
+                            if !touch_requested {
+                                conv::info(pamh, &msg);
+                                touch_requested = true;
+                            }
+                        } else {
+                            conv::info(pamh, &msg);
+                            sys_info(pamh, &msg);
                         }
-                    } else {
-                        println!("{msg}");
-                        sys_info(pamh, &msg);
                     }
-                }
-                PamReq::Err(err) => {
-                    eprintln!("{err}");
-                    sys_err(pamh, &err);
-                }
-                PamReq::GetPin(ack) => loop {
-                    match pamh.get_authtok(Some("Passkey PIN: ")).unwrap() {
-                        None => {
-                            println!("PIN must not be empty");
-                        }
-                        Some(cstr) => {
-                            let p = cstr.to_str().unwrap_or_default().to_string();
-                            ack.send_async(p).await?;
-                            break;
+                    PamReq::Err(err) => {
+                        conv::error(pamh, &err);
+                        sys_err(pamh, &err);
+                    }
+                    PamReq::GetPin(ack) => {
+                        let pin = Self::request_pin(pamh)?;
+                        if ack.send_async(pin).await.is_err() {
+                            sys_err(
+                                pamh,
+                                "Passkey device thread exited before receiving the PIN",
+                            );
+                            return Err(PamError::SERVICE_ERR);
                         }
                     }
-                },
-                PamReq::Result(pk_cred) => {
-                    return Ok(pk_cred);
+                    PamReq::Result(pk_cred) => {
+                        return Ok(pk_cred);
+                    }
+                    PamReq::Exhausted => {
+                        conv::error(pamh, "Passkey validation failed too many times");
+                        sys_err(pamh, "Passkey validation failed too many times");
+                        return Err(PamError::MAXTRIES);
+                    }
                 }
             }
+        })
+        .await
+    }
+
+    fn request_pin(pamh: &Pam) -> Result<String, PamError> {
+        for _ in 0..MAX_ATTEMPTS {
+            match conv::prompt_secret(pamh, "Passkey PIN: ") {
+                Ok(Some(pin)) => return Ok(pin),
+                Ok(None) => {}
+                Err(err) => {
+                    // the user aborted the conversation, e.g. by closing the prompt
+                    sys_info(pamh, &format!("Passkey PIN conversation aborted: {err}"));
+                    return Err(err);
+                }
+            }
+            conv::error(pamh, "PIN must not be empty");
         }
+
+        Err(PamError::MAXTRIES)
     }
 
-    pub async fn wait_for_passkey<U: UiCallback>(ui: &U) -> CtapAuthenticator<'_, USBToken, U> {
+    pub async fn wait_for_passkey<'a, U: UiCallback>(
+        pamh: &Pam,
+        ui: &'a U,
+        deadline: Instant,
+    ) -> Result<CtapAuthenticator<'a, USBToken, U>, PamError> {
         use futures::StreamExt;
 
-        let reader = USBTransport::new().await.unwrap();
+        let deadline = deadline.min(Instant::now() + PASSKEY_INSERT_TIMEOUT);
 
-        loop {
-            match reader.watch().await {
-                Ok(mut tokens) => {
-                    while let Some(event) = tokens.next().await {
-                        match event {
-                            TokenEvent::Added(token) => {
-                                let auth = CtapAuthenticator::new(token, ui).await;
+        with_deadline(pamh, deadline, async {
+            let reader = USBTransport::new().await.map_err(|err| {
+                sys_err(pamh, &format!("Cannot open USB transport: {err:?}"));
+                PamError::AUTHINFO_UNAVAIL
+            })?;
 
-                                if let Some(auth) = auth {
-                                    return auth;
-                                }
-                            }
+            let mut insert_requested = false;
+            loop {
+                let mut tokens = reader.watch().await.map_err(|err| {
+                    sys_err(pamh, &format!("Cannot watch for USB Passkeys: {err:?}"));
+                    PamError::AUTHINFO_UNAVAIL
+                })?;
 
-                            TokenEvent::EnumerationComplete => {
-                                println!(
-                                    "device enumeration completed without detecting a FIDO2 authenticator, connect one to authenticate!"
-                                );
+                while let Some(event) = tokens.next().await {
+                    match event {
+                        TokenEvent::Added(token) => {
+                            let auth = CtapAuthenticator::new(token, ui).await;
+
+                            if let Some(auth) = auth {
+                                return Ok(auth);
                             }
+                        }
 
-                            TokenEvent::Removed(_) => {}
+                        TokenEvent::EnumerationComplete => {
+                            if !insert_requested {
+                                conv::info(pamh, "No Passkey found, connect one to authenticate");
+                                insert_requested = true;
+                            }
                         }
+
+                        TokenEvent::Removed(_) => {}
                     }
                 }
-                Err(e) => panic!("Error: {e:?}"),
+
+                if !insert_requested {
+                    conv::info(pamh, "Insert Passkey");
+                    insert_requested = true;
+                }
+                // the stream may end right away, don't spin on the USB bus
+                time::sleep(Duration::from_millis(250)).await;
             }
+        })
+        .await
+    }
+}
 
-            eprintln!("Insert Passkey");
-        }
+impl PamWebauthn {
+    /// Sends a message to the flow the current device thread belongs to, if any.
+    fn send(&self, req: PamReq) {
+        FLOW.with_borrow(|flow| {
+            if let Some((tx, _)) = flow {
+                let _ = tx.send(req);
+            }
+        });
     }
 }
 
 impl UiCallback for PamWebauthn {
     fn request_pin(&self) -> Option<String> {
+        let (tx, deadline) = FLOW.with_borrow(|flow| flow.clone())?;
+
         let (ack, rx) = flume::bounded(1);
-        self.tx.send(PamReq::GetPin(ack)).unwrap();
-        rx.recv().ok()
+        tx.send(PamReq::GetPin(ack)).ok()?;
+        rx.recv_deadline(deadline).ok()
     }
 
     fn request_touch(&self) {
-        self.tx
-            .send(PamReq::Info("Touch Passkey".to_string()))
-            .unwrap()
+        self.send(PamReq::Info("Touch Passkey".to_string()));
     }
 
     fn processing(&self) {
-        // self.tx
-        //     .send(PamReq::Info("Processing ...".to_string()))
-        //     .unwrap()
+        // self.send(PamReq::Info("Processing ...".to_string()));
     }
 
     fn fingerprint_enrollment_feedback(
@@ -167,47 +256,55 @@ impl UiCallback for PamWebauthn {
         _remaining_samples: u32,
         _feedback: Option<EnrollSampleStatus>,
     ) {
-        unreachable!("Fingerprint Enrollment not supported")
+        self.send(PamReq::Err(
+            "Fingerprint Enrollment is not supported".to_string(),
+        ));
     }
 
     fn cable_qr_code(&self, _request_type: CableRequestType, _url: String) {
-        // match request_type {
-        //     CableRequestType::DiscoverableMakeCredential | CableRequestType::MakeCredential => {
-        //         println!(
-        //             "Scan the QR code with your mobile device to create a new credential with caBLE:"
-        //         );
-        //     }
-        //     CableRequestType::GetAssertion => {
-        //         println!("Scan the QR code with your mobile device to sign in with caBLE:");
-        //     }
-        // }
-        // println!("This feature requires Android with Google Play, or iOS 16 or later.");
-        //
-        // {
-        //     let qr = QrCode::new(&url).expect("Could not create QR code");
-        //
-        //     let code = qr
-        //         .render::<Dense1x2>()
-        //         .dark_color(Dense1x2::Light)
-        //         .light_color(Dense1x2::Dark)
-        //         .build();
-        //
-        //     println!("{}", code);
-        // }
-        todo!("cable_qr_code")
+        // caBLE would need a way to display a QR code, which we don't have inside PAM
+        self.send(PamReq::Err(
+            "caBLE / hybrid authenticators are not supported".to_string(),
+        ));
     }
 
     fn dismiss_qr_code(&self) {
-        self.tx
-            .send(PamReq::Info(
-                "caBLE authenticator detected, connecting...".to_string(),
-            ))
-            .unwrap()
+        self.send(PamReq::Info(
+            "caBLE authenticator detected, connecting...".to_string(),
+        ));
     }
 
     fn cable_status_update(&self, state: CableState) {
-        self.tx
-            .send(PamReq::Info(format!("caBLE status: {state:?}")))
-            .unwrap()
+        self.send(PamReq::Info(format!("caBLE status: {state:?}")));
+    }
+}
+
+fn millis(d: Duration) -> u32 {
+    u32::try_from(d.as_millis()).unwrap_or(u32::MAX)
+}
+
+/// Tells the device thread to not start another attempt once we stopped listening.
+#[derive(Default)]
+struct AbortOnDrop(Arc<AtomicBool>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs the given future until it resolves or the `deadline` is reached. Cancellation by the
+/// user happens through the PAM conversation, which is aborted by the application.
+async fn with_deadline<T, F>(pamh: &Pam, deadline: Instant, fut: F) -> Result<T, PamError>
+where
+    F: Future<Output = Result<T, PamError>>,
+{
+    tokio::select! {
+        res = fut => res,
+        _ = time::sleep_until(deadline) => {
+            conv::error(pamh, "Timeout while waiting for the Passkey");
+            sys_err(pamh, "Timeout while waiting for the Passkey");
+            Err(PamError::AUTH_ERR)
+        }
     }
 }
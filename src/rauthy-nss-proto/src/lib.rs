@@ -0,0 +1,9 @@
+//! The shared types and wire protocol between the `rauthy-nss` proxy and the NSS module.
+
+mod protocol;
+mod snapshot;
+mod types;
+
+pub use protocol::*;
+pub use snapshot::*;
+pub use types::*;
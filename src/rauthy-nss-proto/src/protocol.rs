@@ -0,0 +1,177 @@
+use crate::{Getent, GetentResponse};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::{Display, Formatter};
+
+/// Sent by both sides right after connecting, followed by the `u16` protocol version (LE).
+pub const MAGIC: [u8; 4] = *b"RNSS";
+/// Bump this with every incompatible change of the framing or the types in this crate.
+pub const PROTOCOL_VERSION: u16 = 2;
+pub const HELLO_LEN: usize = MAGIC.len() + 2;
+/// Every frame starts with its body length as `u32` (LE).
+pub const HEADER_LEN: usize = 4;
+/// Protects both sides from allocating huge buffers because of a broken peer.
+pub const MAX_FRAME_LEN: usize = 32 * 1024 * 1024;
+/// A request only ever contains a single name or id. The server must never allocate more
+/// than this for anyone, who is able to connect.
+pub const MAX_REQUEST_LEN: usize = 4096;
+
+// Connection flow:
+//
+// client -> server: hello
+// server -> client: hello (the server closes the connection, if it cannot speak the
+//                   client's version)
+// client -> server: [len][bincode(Getent)]
+// server -> client: [len][status][payload]
+// ... repeated for as long as the connection stays open
+
+/// Maps 1:1 to the NSS status codes, so glibc can tell "does not exist" apart from
+/// "cannot be resolved right now", e.g. for `[NOTFOUND=return]` in `nsswitch.conf`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Status {
+    /// The payload is a bincode encoded `GetentResponse`.
+    Ok = 0,
+    /// The value does not exist. Empty payload
+    NotFound = 1,
+    /// The lookup failed for good, e.g. because of an invalid request or a misconfigured
+    /// host. The payload is a UTF-8 error message.
+    Unavailable = 2,
+    /// A temporary failure like an unreachable Rauthy or a rate limit. The payload is a
+    /// UTF-8 error message.
+    TryAgain = 3,
+}
+
+impl TryFrom<u8> for Status {
+    type Error = ProtoError;
+
+    fn try_from(value: u8) -> Result<Self, ProtoError> {
+        match value {
+            0 => Ok(Self::Ok),
+            1 => Ok(Self::NotFound),
+            2 => Ok(Self::Unavailable),
+            3 => Ok(Self::TryAgain),
+            v => Err(ProtoError::Invalid(format!("unknown status {v}"))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProtoError {
+    Invalid(String),
+    Version(u16),
+    TooLarge(usize),
+}
+
+impl Display for ProtoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(msg) => write!(f, "invalid message: {msg}"),
+            Self::Version(v) => write!(
+                f,
+                "unsupported protocol version {v}, expected {PROTOCOL_VERSION}"
+            ),
+            Self::TooLarge(len) => write!(f, "frame with {len} bytes exceeds {MAX_FRAME_LEN}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+pub fn hello() -> [u8; HELLO_LEN] {
+    let mut buf = [0; HELLO_LEN];
+    buf[..MAGIC.len()].copy_from_slice(&MAGIC);
+    buf[MAGIC.len()..].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    buf
+}
+
+/// Returns the peer's protocol version.
+pub fn parse_hello(buf: &[u8; HELLO_LEN]) -> Result<u16, ProtoError> {
+    if buf[..MAGIC.len()] != MAGIC {
+        return Err(ProtoError::Invalid("bad magic".to_string()));
+    }
+    Ok(u16::from_le_bytes([buf[MAGIC.len()], buf[MAGIC.len() + 1]]))
+}
+
+/// Returns the body length from a frame header.
+pub fn parse_header(buf: &[u8; HEADER_LEN]) -> Result<usize, ProtoError> {
+    let len = u32::from_le_bytes(*buf) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtoError::TooLarge(len));
+    }
+    Ok(len)
+}
+
+/// Returns the body length from a request frame header.
+pub fn parse_request_header(buf: &[u8; HEADER_LEN]) -> Result<usize, ProtoError> {
+    let len = u32::from_le_bytes(*buf) as usize;
+    if len > MAX_REQUEST_LEN {
+        return Err(ProtoError::Invalid(format!(
+            "request with {len} bytes exceeds {MAX_REQUEST_LEN}"
+        )));
+    }
+    Ok(len)
+}
+
+/// A complete request frame including the header.
+pub fn encode_request(getent: &Getent) -> Result<Vec<u8>, ProtoError> {
+    let body = encode(getent)?;
+    if body.len() > MAX_REQUEST_LEN {
+        return Err(ProtoError::Invalid(format!(
+            "request with {} bytes exceeds {MAX_REQUEST_LEN}",
+            body.len()
+        )));
+    }
+    frame(None, &body)
+}
+
+pub fn decode_request(body: &[u8]) -> Result<Getent, ProtoError> {
+    decode(body)
+}
+
+/// A complete response frame including the header. For `Status::Ok`, the `payload` must be
+/// an already encoded `GetentResponse`, which allows sending cached values as they are.
+pub fn encode_response(status: Status, payload: &[u8]) -> Result<Vec<u8>, ProtoError> {
+    frame(Some(status), payload)
+}
+
+pub fn decode_response(body: &[u8]) -> Result<(Status, &[u8]), ProtoError> {
+    let Some((status, payload)) = body.split_first() else {
+        return Err(ProtoError::Invalid("empty response".to_string()));
+    };
+    Ok((Status::try_from(*status)?, payload))
+}
+
+pub fn encode_getent_response(resp: &GetentResponse) -> Result<Vec<u8>, ProtoError> {
+    encode(resp)
+}
+
+pub fn decode_getent_response(payload: &[u8]) -> Result<GetentResponse, ProtoError> {
+    decode(payload)
+}
+
+fn frame(status: Option<Status>, payload: &[u8]) -> Result<Vec<u8>, ProtoError> {
+    let len = payload.len() + status.is_some() as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtoError::TooLarge(len));
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + len);
+    buf.extend_from_slice(&(len as u32).to_le_bytes());
+    if let Some(status) = status {
+        buf.push(status as u8);
+    }
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, ProtoError> {
+    bincode::serde::encode_to_vec(value, bincode::config::standard())
+        .map_err(|err| ProtoError::Invalid(err.to_string()))
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ProtoError> {
+    bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+        .map(|(v, _)| v)
+        .map_err(|err| ProtoError::Invalid(err.to_string()))
+}
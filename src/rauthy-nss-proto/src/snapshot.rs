@@ -0,0 +1,252 @@
+use crate::protocol::{decode_getent_response, encode_getent_response};
+use crate::{Getent, GetentResponse, GroupResponse, HostResponse, ProtoError, UserResponse};
+
+// A read-only snapshot of all users, groups and hosts, which the proxy publishes as a file
+// and the NSS module maps into memory to resolve lookups without any IPC.
+//
+// Layout (all integers LE):
+//
+// 0   magic `RNSC`
+// 4   u16 version
+// 8   u32 stale flag - set by the proxy in the old file after it has been replaced
+// 16  i64 unix timestamp after which the snapshot must not be used anymore
+// 24  9 x (u32 offset, u32 len) for each `Table`
+// 96  index entries and records
+//
+// Index tables are sorted arrays of `(u64 key, u32 offset, u32 len)`, where the offset and
+// len point to a bincode encoded `GetentResponse`, exactly like the proxy would send it.
+// Keys are either ids or a hash of the name, which is why each hit must be verified.
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"RNSC";
+pub const SNAPSHOT_VERSION: u16 = 1;
+/// Offset of the `u32` stale flag. It is 4 byte aligned inside a page aligned mapping.
+pub const SNAPSHOT_STALE_OFFSET: usize = 8;
+const EXPIRES_OFFSET: usize = 16;
+const TABLES_OFFSET: usize = 24;
+const TABLES: usize = 9;
+pub const SNAPSHOT_HEADER_LEN: usize = TABLES_OFFSET + TABLES * 8;
+const ENTRY_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+enum Table {
+    UsersById,
+    UsersByName,
+    GroupsById,
+    GroupsByName,
+    HostsByName,
+    HostsByIp,
+    Users,
+    Groups,
+    Hosts,
+}
+
+/// Builds a complete snapshot file.
+pub fn encode_snapshot(
+    users: &[UserResponse],
+    groups: &[GroupResponse],
+    hosts: &[HostResponse],
+    expires: i64,
+) -> Result<Vec<u8>, ProtoError> {
+    let mut records = Vec::with_capacity(users.len() + groups.len() + hosts.len());
+    let mut indexes: [Vec<(u64, usize)>; 6] = Default::default();
+
+    for user in users {
+        let idx = records.len();
+        records.push(encode_getent_response(&GetentResponse::User(user.clone()))?);
+        indexes[Table::UsersById as usize].push((user.id as u64, idx));
+        indexes[Table::UsersByName as usize].push((hash(&user.name), idx));
+    }
+    for group in groups {
+        let idx = records.len();
+        records.push(encode_getent_response(&GetentResponse::Group(
+            group.clone(),
+        ))?);
+        indexes[Table::GroupsById as usize].push((group.id as u64, idx));
+        indexes[Table::GroupsByName as usize].push((hash(&group.name), idx));
+    }
+    for host in hosts {
+        let idx = records.len();
+        records.push(encode_getent_response(&GetentResponse::Host(host.clone()))?);
+        for name in std::iter::once(&host.name).chain(host.aliases.iter()) {
+            indexes[Table::HostsByName as usize].push((hash(name), idx));
+        }
+        for addr in &host.addresses {
+            indexes[Table::HostsByIp as usize].push((hash(&addr.to_string()), idx));
+        }
+    }
+
+    let lists = [
+        encode_getent_response(&GetentResponse::Users(users.to_vec()))?,
+        encode_getent_response(&GetentResponse::Groups(groups.to_vec()))?,
+        encode_getent_response(&GetentResponse::Hosts(hosts.to_vec()))?,
+    ];
+
+    // records start right after all index tables
+    let entries = indexes.iter().map(|i| i.len()).sum::<usize>();
+    let mut offset = SNAPSHOT_HEADER_LEN + entries * ENTRY_LEN;
+    let mut record_offsets = Vec::with_capacity(records.len());
+    for rec in &records {
+        record_offsets.push(offset);
+        offset += rec.len();
+    }
+    let mut list_offsets = Vec::with_capacity(lists.len());
+    for list in &lists {
+        list_offsets.push(offset);
+        offset += list.len();
+    }
+    let total = offset;
+    if total > u32::MAX as usize {
+        return Err(ProtoError::TooLarge(total));
+    }
+
+    let mut buf = Vec::with_capacity(total);
+    buf.extend_from_slice(&SNAPSHOT_MAGIC);
+    buf.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    buf.extend_from_slice(&[0; 2]);
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(&expires.to_le_bytes());
+
+    let mut table_offset = SNAPSHOT_HEADER_LEN;
+    for index in &indexes {
+        buf.extend_from_slice(&(table_offset as u32).to_le_bytes());
+        buf.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        table_offset += index.len() * ENTRY_LEN;
+    }
+    for (list, offset) in lists.iter().zip(&list_offsets) {
+        buf.extend_from_slice(&(*offset as u32).to_le_bytes());
+        buf.extend_from_slice(&(list.len() as u32).to_le_bytes());
+    }
+
+    for index in indexes.iter_mut() {
+        index.sort_by_key(|(key, _)| *key);
+        for (key, idx) in index.iter() {
+            buf.extend_from_slice(&key.to_le_bytes());
+            buf.extend_from_slice(&(record_offsets[*idx] as u32).to_le_bytes());
+            buf.extend_from_slice(&(records[*idx].len() as u32).to_le_bytes());
+        }
+    }
+    for rec in records.iter().chain(lists.iter()) {
+        buf.extend_from_slice(rec);
+    }
+    debug_assert_eq!(buf.len(), total);
+
+    Ok(buf)
+}
+
+/// A validated view into snapshot bytes. All reads are bounds checked, so even a corrupted
+/// file can never crash the process using it.
+pub struct Snapshot<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Snapshot<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, ProtoError> {
+        if buf.len() < SNAPSHOT_HEADER_LEN || buf[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+            return Err(ProtoError::Invalid("bad snapshot header".to_string()));
+        }
+        let version = u16::from_le_bytes([buf[4], buf[5]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(ProtoError::Version(version));
+        }
+        Ok(Self { buf })
+    }
+
+    pub fn expires(&self) -> i64 {
+        i64::from_le_bytes(
+            self.buf[EXPIRES_OFFSET..EXPIRES_OFFSET + 8]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// `None` if the value is not part of this snapshot, which is not the same as
+    /// "does not exist".
+    pub fn get(&self, getent: &Getent) -> Option<GetentResponse> {
+        match getent {
+            Getent::Users => self.list(Table::Users),
+            Getent::Username(name) => self.find(Table::UsersByName, hash(name), |r| {
+                matches!(r, GetentResponse::User(u) if &u.name == name)
+            }),
+            Getent::UserId(uid) => self.find(Table::UsersById, *uid as u64, |r| {
+                matches!(r, GetentResponse::User(u) if u.id == *uid)
+            }),
+            Getent::Groups => self.list(Table::Groups),
+            Getent::Groupname(name) => self.find(Table::GroupsByName, hash(name), |r| {
+                matches!(r, GetentResponse::Group(g) if &g.name == name)
+            }),
+            Getent::GroupId(gid) => self.find(Table::GroupsById, *gid as u64, |r| {
+                matches!(r, GetentResponse::Group(g) if g.id == *gid)
+            }),
+            Getent::Hosts => self.list(Table::Hosts),
+            Getent::Hostname(name) => self.find(Table::HostsByName, hash(name), |r| {
+                matches!(r, GetentResponse::Host(h) if &h.name == name || h.aliases.contains(name))
+            }),
+            Getent::HostIp(ip) => self.find(Table::HostsByIp, hash(&ip.to_string()), |r| {
+                matches!(r, GetentResponse::Host(h) if h.addresses.contains(ip))
+            }),
+            Getent::Netgroups | Getent::Netgroupname(_) => None,
+        }
+    }
+
+    fn find<F>(&self, table: Table, key: u64, matches: F) -> Option<GetentResponse>
+    where
+        F: Fn(&GetentResponse) -> bool,
+    {
+        let (offset, count) = self.table(table)?;
+        let entries = self
+            .buf
+            .get(offset..offset.checked_add(count.checked_mul(ENTRY_LEN)?)?)?;
+        let entry = |i: usize| -> (u64, usize, usize) {
+            let e = &entries[i * ENTRY_LEN..(i + 1) * ENTRY_LEN];
+            (
+                u64::from_le_bytes(e[..8].try_into().unwrap()),
+                u32::from_le_bytes(e[8..12].try_into().unwrap()) as usize,
+                u32::from_le_bytes(e[12..].try_into().unwrap()) as usize,
+            )
+        };
+
+        // lower bound binary search, hash collisions are next to each other
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if entry(mid).0 < key {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        (lo..count)
+            .map(entry)
+            .take_while(|(k, _, _)| *k == key)
+            .filter_map(|(_, offset, len)| self.decode(offset, len))
+            .find(|resp| matches(resp))
+    }
+
+    fn list(&self, table: Table) -> Option<GetentResponse> {
+        let (offset, len) = self.table(table)?;
+        self.decode(offset, len)
+    }
+
+    fn decode(&self, offset: usize, len: usize) -> Option<GetentResponse> {
+        let bytes = self.buf.get(offset..offset.checked_add(len)?)?;
+        decode_getent_response(bytes).ok()
+    }
+
+    fn table(&self, table: Table) -> Option<(usize, usize)> {
+        let pos = TABLES_OFFSET + table as usize * 8;
+        let b = self.buf.get(pos..pos + 8)?;
+        Some((
+            u32::from_le_bytes(b[..4].try_into().unwrap()) as usize,
+            u32::from_le_bytes(b[4..].try_into().unwrap()) as usize,
+        ))
+    }
+}
+
+/// FNV-1a, which is stable across builds and platforms, unlike the std `Hasher`s.
+fn hash(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
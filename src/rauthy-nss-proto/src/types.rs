@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+// These types are sent as JSON to / from Rauthy and as bincode between the NSS module and
+// the proxy. Never reorder enum variants - bincode encodes them by index.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Getent {
+    Users,
+    Username(String),
+    UserId(u32),
+    Groups,
+    Groupname(String),
+    GroupId(u32),
+    Hosts,
+    Hostname(String),
+    HostIp(IpAddr),
+    Netgroups,
+    Netgroupname(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostResponse {
+    pub id: String,
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub addresses: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupType {
+    Immutable,
+    Host,
+    User,
+    Generic,
+    Local,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupResponse {
+    pub id: u32,
+    pub name: String,
+    pub typ: GroupType,
+    pub members: Vec<String>,
+}
+
+/// A netgroup derived from a Rauthy `host` group. `hosts` are the hostnames of all hosts in
+/// this group, `users` all users with access to them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetgroupResponse {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub users: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserResponse {
+    pub id: u32,
+    pub name: String,
+    pub gid: u32,
+    pub email: String,
+    pub shell: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GetentResponse {
+    Users(Vec<UserResponse>),
+    User(UserResponse),
+    Groups(Vec<GroupResponse>),
+    Group(GroupResponse),
+    Hosts(Vec<HostResponse>),
+    Host(HostResponse),
+    Netgroups(Vec<NetgroupResponse>),
+    Netgroup(NetgroupResponse),
+}
@@ -1,18 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 
-#[derive(Debug, Serialize)]
-pub enum Getent {
-    Users,
-    Username(String),
-    UserId(u32),
-    Groups,
-    Groupname(String),
-    GroupId(u32),
-    Hosts,
-    Hostname(String),
-    HostIp(IpAddr),
-}
+pub use rauthy_nss_proto::{Getent, GetentResponse, GroupResponse, GroupType, UserResponse};
 
 #[derive(Debug, Serialize)]
 pub struct GetentRequest<'a> {
@@ -26,13 +15,34 @@ pub struct HostWhoamiRequest<'a> {
     pub host_secret: &'a str,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HostResponse {
-    pub id: String,
-    pub name: String,
-    pub aliases: Vec<String>,
-    pub addresses: Vec<IpAddr>,
+#[derive(Debug, Serialize)]
+pub struct HostEventsRequest<'a> {
+    pub host_secret: &'a str,
+}
+
+/// A change in Rauthy, pushed as the `data` of a server-sent event.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "typ", rename_all = "snake_case")]
+pub enum RauthyEvent {
+    UserChanged {
+        name: String,
+        uid: u32,
+    },
+    UserDisabled {
+        name: String,
+        uid: u32,
+    },
+    UserDeleted {
+        name: String,
+        uid: u32,
+    },
+    GroupChanged {
+        name: String,
+        gid: u32,
+    },
+    HostsChanged,
+    /// Anything else, which cannot be mapped to single cache entries.
+    Reset,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,41 +54,26 @@ pub struct HostDetailsResponse {
     pub notes: Option<String>,
     pub ips: Vec<IpAddr>,
     pub aliases: Vec<String>,
+    // older Rauthy versions do not send any sudo rules
+    #[serde(default)]
+    pub sudo_rules: Vec<SudoRule>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum GroupType {
-    Immutable,
-    Host,
-    User,
-    Generic,
-    Local,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GroupResponse {
-    pub id: u32,
-    pub name: String,
-    pub typ: GroupType,
-    pub members: Vec<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UserResponse {
-    pub id: u32,
-    pub name: String,
-    pub gid: u32,
-    pub email: String,
-    pub shell: String,
+/// The part of `HostDetailsResponse` any local user is allowed to see.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HostPublicResponse {
+    pub hostname: String,
+    pub force_mfa: bool,
+    pub aliases: Vec<String>,
 }
 
+/// A sudo rule for members of `group` on this host, rendered as
+/// `%<group> ALL=(<runas>) [NOPASSWD: ]<commands>`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum GetentResponse {
-    Users(Vec<UserResponse>),
-    User(UserResponse),
-    Groups(Vec<GroupResponse>),
-    Group(GroupResponse),
-    Hosts(Vec<HostResponse>),
-    Host(HostResponse),
+pub struct SudoRule {
+    pub group: String,
+    pub runas: Option<String>,
+    #[serde(default)]
+    pub nopasswd: bool,
+    pub commands: Vec<String>,
 }
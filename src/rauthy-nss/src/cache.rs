@@ -1,7 +1,8 @@
 use crate::config::Config;
 use chrono::Utc;
-use log::info;
-use std::collections::HashMap;
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::OnceLock;
 use std::thread;
 use std::time::Duration;
@@ -10,11 +11,28 @@ use tokio::{task, time};
 
 static TX: OnceLock<flume::Sender<CacheReq>> = OnceLock::new();
 
+/// Rough per-entry overhead of the maps on top of the key and value.
+const ENTRY_OVERHEAD: usize = 96;
+
 #[derive(Debug)]
 pub struct CacheValue {
     pub exp: i64,
     // we are saving Options to impl negative caching
     pub value: Option<Vec<u8>>,
+    /// position in the LRU order
+    tick: u64,
+    size: usize,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub negative_entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
 }
 
 #[derive(Debug)]
@@ -28,7 +46,183 @@ pub enum CacheReq {
         value: Option<Vec<u8>>,
         ttl: u32,
     },
+    PutMany {
+        entries: Vec<(String, Option<Vec<u8>>)>,
+        ttl: u32,
+    },
+    Remove {
+        keys: Vec<String>,
+    },
+    Clear,
     Flush,
+    Stats {
+        ack: oneshot::Sender<CacheStats>,
+    },
+}
+
+/// The actual storage, only ever accessed from the cache thread.
+///
+/// Entries are evicted in LRU order as soon as one of the limits is exceeded. Negative
+/// entries have their own, much lower limit, because anyone can create them with lookups of
+/// random names.
+struct Store {
+    data: HashMap<String, CacheValue>,
+    lru: BTreeMap<u64, String>,
+    lru_negative: BTreeMap<u64, String>,
+    tick: u64,
+    max_entries: usize,
+    max_negative: usize,
+    max_bytes: usize,
+    stats: CacheStats,
+}
+
+impl Store {
+    fn new() -> Self {
+        let config = Config::get();
+        Self {
+            data: HashMap::with_capacity(128),
+            lru: BTreeMap::new(),
+            lru_negative: BTreeMap::new(),
+            tick: 0,
+            max_entries: config.cache_max_entries.max(1),
+            max_negative: config.cache_max_negative_entries,
+            max_bytes: config.cache_max_bytes,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Option<Vec<u8>>> {
+        let now = Utc::now().timestamp();
+        let Some(v) = self.data.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        if v.exp <= now {
+            self.stats.misses += 1;
+            self.stats.expirations += 1;
+            self.remove(key);
+            return None;
+        }
+
+        self.tick += 1;
+        let lru = if v.value.is_some() {
+            &mut self.lru
+        } else {
+            &mut self.lru_negative
+        };
+        if let Some(k) = lru.remove(&v.tick) {
+            lru.insert(self.tick, k);
+        }
+        v.tick = self.tick;
+
+        self.stats.hits += 1;
+        Some(v.value.clone())
+    }
+
+    fn put(&mut self, key: String, value: Option<Vec<u8>>, ttl: u32) {
+        self.remove(&key);
+
+        let size = key.len() + value.as_ref().map(|v| v.len()).unwrap_or(0) + ENTRY_OVERHEAD;
+        if size > self.max_bytes {
+            debug!("Cache value for {key} with {size} bytes exceeds the whole budget");
+            return;
+        }
+        let is_negative = value.is_none();
+
+        self.tick += 1;
+        if is_negative {
+            if self.max_negative == 0 {
+                return;
+            }
+            self.lru_negative.insert(self.tick, key.clone());
+        } else {
+            self.lru.insert(self.tick, key.clone());
+        }
+        self.data.insert(
+            key,
+            CacheValue {
+                exp: Utc::now().timestamp() + ttl as i64,
+                value,
+                tick: self.tick,
+                size,
+            },
+        );
+        self.stats.bytes += size;
+        if is_negative {
+            self.stats.negative_entries += 1;
+        }
+
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.lru_negative.len() > self.max_negative {
+            self.evict_oldest(true);
+        }
+        while self.data.len() > self.max_entries || self.stats.bytes > self.max_bytes {
+            // negative entries are cheap to re-create, so they go first
+            let negative = !self.lru_negative.is_empty();
+            if !self.evict_oldest(negative) {
+                break;
+            }
+        }
+    }
+
+    fn evict_oldest(&mut self, negative: bool) -> bool {
+        let lru = if negative {
+            &mut self.lru_negative
+        } else {
+            &mut self.lru
+        };
+        let Some((_, key)) = lru.pop_first() else {
+            return false;
+        };
+        self.remove(&key);
+        self.stats.evictions += 1;
+        true
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(v) = self.data.remove(key) {
+            self.stats.bytes -= v.size;
+            if v.value.is_some() {
+                self.lru.remove(&v.tick);
+            } else {
+                self.lru_negative.remove(&v.tick);
+                self.stats.negative_entries -= 1;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        let now = Utc::now().timestamp();
+
+        let remove = self
+            .data
+            .iter()
+            .filter_map(|(k, v)| if v.exp <= now { Some(k.clone()) } else { None })
+            .collect::<Vec<_>>();
+
+        self.stats.expirations += remove.len() as u64;
+        for key in remove {
+            self.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.lru.clear();
+        self.lru_negative.clear();
+        self.stats.bytes = 0;
+        self.stats.negative_entries = 0;
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.data.len(),
+            ..self.stats.clone()
+        }
+    }
 }
 
 pub struct Cache;
@@ -52,42 +246,40 @@ impl Cache {
     }
 
     fn handler(rx: flume::Receiver<CacheReq>) {
-        let mut data: HashMap<String, CacheValue> = HashMap::with_capacity(128);
+        let mut store = Store::new();
 
         while let Ok(req) = rx.recv() {
             match req {
-                CacheReq::Get { ack, key } => match data.get(&key) {
-                    None => {
-                        ack.send(None).unwrap();
+                CacheReq::Get { ack, key } => {
+                    let _ = ack.send(store.get(&key));
+                }
+                CacheReq::Put { key, value, ttl } => store.put(key, value, ttl),
+                CacheReq::PutMany { entries, ttl } => {
+                    for (key, value) in entries {
+                        store.put(key, value, ttl);
                     }
-                    Some(v) => {
-                        if v.exp > Utc::now().timestamp() {
-                            ack.send(Some(v.value.clone())).unwrap();
-                        } else {
-                            ack.send(None).unwrap();
-                        }
+                }
+                CacheReq::Remove { keys } => {
+                    for key in keys {
+                        store.remove(&key);
                     }
-                },
-                CacheReq::Put { key, value, ttl } => {
-                    data.insert(
-                        key,
-                        CacheValue {
-                            exp: Utc::now().timestamp() + ttl as i64,
-                            value,
-                        },
-                    );
                 }
+                CacheReq::Clear => store.clear(),
                 CacheReq::Flush => {
-                    let now = Utc::now().timestamp();
-
-                    let remove = data
-                        .iter()
-                        .filter_map(|(k, v)| if v.exp > now { Some(k.clone()) } else { None })
-                        .collect::<Vec<_>>();
-
-                    for key in remove {
-                        data.remove(&key);
-                    }
+                    store.flush();
+                    let stats = store.stats();
+                    info!(
+                        "Cache: {} entries ({} negative), {} bytes, {} hits, {} misses, {} evictions",
+                        stats.entries,
+                        stats.negative_entries,
+                        stats.bytes,
+                        stats.hits,
+                        stats.misses,
+                        stats.evictions,
+                    );
+                }
+                CacheReq::Stats { ack } => {
+                    let _ = ack.send(store.stats());
                 }
             }
         }
@@ -107,6 +299,18 @@ impl Cache {
         rx.await.ok()?
     }
 
+    pub async fn stats() -> Option<CacheStats> {
+        let (ack, rx) = oneshot::channel();
+
+        TX.get()
+            .unwrap()
+            .send_async(CacheReq::Stats { ack })
+            .await
+            .ok()?;
+
+        rx.await.ok()
+    }
+
     #[inline]
     pub async fn set(key: String, value: Option<Vec<u8>>, ttl: u32) {
         let _ = TX
@@ -115,4 +319,30 @@ impl Cache {
             .send_async(CacheReq::Put { key, value, ttl })
             .await;
     }
+
+    /// Sends all entries at once, which is much cheaper than one `set()` each for big lists.
+    #[inline]
+    pub async fn set_many(entries: Vec<(String, Option<Vec<u8>>)>, ttl: u32) {
+        if entries.is_empty() {
+            return;
+        }
+        let _ = TX
+            .get()
+            .unwrap()
+            .send_async(CacheReq::PutMany { entries, ttl })
+            .await;
+    }
+
+    /// Removes the given keys immediately, e.g. after Rauthy notified us about changes.
+    pub async fn remove(keys: Vec<String>) {
+        let _ = TX
+            .get()
+            .unwrap()
+            .send_async(CacheReq::Remove { keys })
+            .await;
+    }
+
+    pub async fn clear() {
+        let _ = TX.get().unwrap().send_async(CacheReq::Clear).await;
+    }
 }
@@ -0,0 +1,199 @@
+use crate::api_types::{GroupResponse, GroupType, UserResponse};
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::error::Error;
+use crate::groups_local::GroupLocal;
+use crate::utils::{deserialize, serialize};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::{LazyLock, Mutex};
+use tokio::fs;
+
+static CACHE_KEY: &str = "$users_local_all$";
+
+/// Conflicts we already reported. They are logged once per process, because the same
+/// conflicting entry will show up with every single lookup.
+static REPORTED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Everything on this host that Rauthy users and groups must never collide with.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LocalIds {
+    /// username -> uid from `/etc/passwd`
+    users: BTreeMap<String, u32>,
+    uids: BTreeSet<u32>,
+    /// inclusive ranges from `/etc/subuid` and `/etc/subgid`
+    sub_ranges: Vec<(u32, u32)>,
+}
+
+impl LocalIds {
+    /// Filters out all users that collide with local ones.
+    pub async fn filter_users(users: Vec<UserResponse>) -> Result<Vec<UserResponse>, Error> {
+        let slf = Self::read().await?;
+        Ok(users
+            .into_iter()
+            .filter(|u| slf.check_user(u).is_ok())
+            .collect())
+    }
+
+    /// Returns `None` if the user collides with a local one.
+    pub async fn filter_user(user: UserResponse) -> Result<Option<UserResponse>, Error> {
+        let slf = Self::read().await?;
+        Ok(slf.check_user(&user).is_ok().then_some(user))
+    }
+
+    /// Filters out all groups that collide with local ones. Groups with type `local` are
+    /// mapped onto existing local groups on purpose and are therefore never checked.
+    pub async fn filter_groups(groups: Vec<GroupResponse>) -> Result<Vec<GroupResponse>, Error> {
+        let slf = Self::read().await?;
+        let locals = GroupLocal::read().await?.unwrap_or_default();
+        Ok(groups
+            .into_iter()
+            .filter(|g| slf.check_group(g, &locals).is_ok())
+            .collect())
+    }
+
+    /// Returns `None` if the group collides with a local one.
+    pub async fn filter_group(group: GroupResponse) -> Result<Option<GroupResponse>, Error> {
+        let slf = Self::read().await?;
+        let locals = GroupLocal::read().await?.unwrap_or_default();
+        Ok(slf.check_group(&group, &locals).is_ok().then_some(group))
+    }
+
+    fn check_user(&self, user: &UserResponse) -> Result<(), ()> {
+        let conflict = if let Some(uid) = self.users.get(&user.name) {
+            Some(format!("name exists locally with uid {uid}"))
+        } else if self.uids.contains(&user.id) {
+            Some("uid exists locally".to_string())
+        } else {
+            self.check_id(user.id)
+        };
+
+        match conflict {
+            None => Ok(()),
+            Some(reason) => {
+                report(format!(
+                    "Refusing Rauthy user {} with uid {}: {reason}",
+                    user.name, user.id
+                ));
+                Err(())
+            }
+        }
+    }
+
+    fn check_group(
+        &self,
+        group: &GroupResponse,
+        locals: &BTreeMap<String, GroupLocal>,
+    ) -> Result<(), ()> {
+        if group.typ == GroupType::Local {
+            return Ok(());
+        }
+
+        let conflict = if let Some(local) = locals.get(&group.name) {
+            Some(format!("name exists locally with gid {}", local.id))
+        } else if locals.values().any(|g| g.id == group.id) {
+            Some("gid exists locally".to_string())
+        } else {
+            self.check_id(group.id)
+        };
+
+        match conflict {
+            None => Ok(()),
+            Some(reason) => {
+                report(format!(
+                    "Refusing Rauthy group {} with gid {}: {reason}",
+                    group.name, group.id
+                ));
+                Err(())
+            }
+        }
+    }
+
+    fn check_id(&self, id: u32) -> Option<String> {
+        let config = Config::get();
+
+        if id < config.id_floor {
+            return Some(format!("below id_floor {}", config.id_floor));
+        }
+        if let Some([start, end]) = config
+            .reserved_id_ranges
+            .iter()
+            .find(|[start, end]| (*start..=*end).contains(&id))
+        {
+            return Some(format!("inside reserved range {start}-{end}"));
+        }
+        if let Some((start, end)) = self
+            .sub_ranges
+            .iter()
+            .find(|(start, end)| (*start..=*end).contains(&id))
+        {
+            return Some(format!(
+                "inside subordinate id range {start}-{end} from /etc/subuid or /etc/subgid"
+            ));
+        }
+
+        None
+    }
+
+    async fn read() -> Result<Self, Error> {
+        if let Some(Some(bytes)) = Cache::get(CACHE_KEY.to_string()).await {
+            return deserialize::<Self>(&bytes);
+        }
+
+        let mut users = BTreeMap::new();
+        let mut uids = BTreeSet::new();
+        for line in fs::read_to_string("/etc/passwd").await?.lines() {
+            let mut parts = line.split(':');
+            if let (Some(name), Some(_x), Some(Ok(uid))) = (
+                parts.next(),
+                parts.next(),
+                parts.next().map(str::parse::<u32>),
+            ) {
+                users.insert(name.to_string(), uid);
+                uids.insert(uid);
+            }
+        }
+
+        let mut sub_ranges = Vec::new();
+        for path in ["/etc/subuid", "/etc/subgid"] {
+            // these files are optional
+            let Ok(content) = fs::read_to_string(path).await else {
+                continue;
+            };
+            for line in content.lines() {
+                let mut parts = line.split(':');
+                if let (Some(_name), Some(Ok(start)), Some(Ok(count))) = (
+                    parts.next(),
+                    parts.next().map(str::parse::<u32>),
+                    parts.next().map(str::parse::<u32>),
+                ) && count > 0
+                {
+                    sub_ranges.push((start, start.saturating_add(count - 1)));
+                }
+            }
+        }
+
+        let slf = Self {
+            users,
+            uids,
+            sub_ranges,
+        };
+        Cache::set(
+            CACHE_KEY.to_string(),
+            Some(serialize(&slf)?),
+            Config::get().cache_ttl_users,
+        )
+        .await;
+
+        Ok(slf)
+    }
+}
+
+fn report(msg: String) {
+    let mut reported = REPORTED.lock().unwrap();
+    if !reported.contains(&msg) {
+        error!("ID COLLISION: {msg}");
+        reported.insert(msg);
+    }
+}
@@ -24,6 +24,15 @@ pub enum LogTarget {
     Syslog,
 }
 
+/// What happens to home dirs of users, that have been deleted in Rauthy.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HomeCleanupAction {
+    #[default]
+    Archive,
+    Delete,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     // pub listen_addr: String,
@@ -40,14 +49,158 @@ pub struct Config {
     pub cache_ttl_hosts: u32,
     pub cache_ttl_users: u32,
     pub cache_flush_interval: u64,
+    #[serde(default = "cache_max_entries")]
+    pub cache_max_entries: usize,
+    #[serde(default = "cache_max_negative_entries")]
+    pub cache_max_negative_entries: usize,
+    #[serde(default = "cache_max_bytes")]
+    pub cache_max_bytes: usize,
+    #[serde(default = "prefetch_interval")]
+    pub prefetch_interval: u64,
+    #[serde(default = "prefetch_max_entries")]
+    pub prefetch_max_entries: usize,
     pub health_check_interval_healthy: u64,
     pub health_check_interval_unhealthy: u64,
+    #[serde(default = "id_floor")]
+    pub id_floor: u32,
+    #[serde(default)]
+    pub reserved_id_ranges: Vec<[u32; 2]>,
+    #[serde(default = "data_path")]
+    pub data_path: PathBuf,
+    #[serde(default = "bool_false")]
+    pub home_cleanup_enable: bool,
+    #[serde(default = "bool_true")]
+    pub home_cleanup_dry_run: bool,
+    #[serde(default)]
+    pub home_cleanup_action: HomeCleanupAction,
+    #[serde(default = "home_cleanup_grace_days")]
+    pub home_cleanup_grace_days: u32,
+    #[serde(default = "home_cleanup_interval")]
+    pub home_cleanup_interval: u64,
+    #[serde(default = "home_cleanup_archive_path")]
+    pub home_cleanup_archive_path: PathBuf,
+    #[serde(default = "bool_false")]
+    pub sudoers_enable: bool,
+    #[serde(default = "sudoers_interval")]
+    pub sudoers_interval: u64,
+    #[serde(default = "bool_false")]
+    pub userdb_enable: bool,
+    #[serde(default = "bool_true")]
+    pub snapshot_enable: bool,
+    #[serde(default = "snapshot_interval")]
+    pub snapshot_interval: u64,
+    #[serde(default = "rate_limit_per_uid")]
+    pub rate_limit_per_uid: u32,
+    #[serde(default = "rate_limit_burst")]
+    pub rate_limit_burst: u32,
+    #[serde(default = "max_in_flight_per_uid")]
+    pub max_in_flight_per_uid: usize,
+    #[serde(default = "max_connections_per_uid")]
+    pub max_connections_per_uid: usize,
+    #[serde(default = "bool_false")]
+    pub restrict_enumeration: bool,
+    pub enumeration_group: Option<String>,
+    #[serde(default = "bool_false")]
+    pub events_enable: bool,
+    #[serde(default = "bool_false")]
+    pub events_terminate_sessions: bool,
+    #[serde(default = "bool_false")]
+    pub session_validate_enable: bool,
+    #[serde(default = "session_validate_interval")]
+    pub session_validate_interval: u64,
+    #[serde(default = "session_terminate_grace")]
+    pub session_terminate_grace: u64,
+    #[serde(default = "session_terminate_message")]
+    pub session_terminate_message: String,
 }
 
 fn bool_false() -> bool {
     false
 }
 
+fn bool_true() -> bool {
+    true
+}
+
+/// Shared with the PAM module, which saves its tokens in here.
+fn data_path() -> PathBuf {
+    "/var/lib/pam_rauthy".into()
+}
+
+fn cache_max_entries() -> usize {
+    100_000
+}
+
+fn cache_max_negative_entries() -> usize {
+    10_000
+}
+
+fn cache_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn session_validate_interval() -> u64 {
+    60
+}
+
+fn session_terminate_grace() -> u64 {
+    30
+}
+
+fn session_terminate_message() -> String {
+    "Your account has been disabled. All your sessions on this host will be terminated in \
+    {grace} seconds."
+        .to_string()
+}
+
+fn prefetch_interval() -> u64 {
+    20
+}
+
+fn prefetch_max_entries() -> usize {
+    5000
+}
+
+fn home_cleanup_grace_days() -> u32 {
+    30
+}
+
+fn home_cleanup_interval() -> u64 {
+    3600
+}
+
+fn home_cleanup_archive_path() -> PathBuf {
+    "/var/lib/rauthy/home_archive".into()
+}
+
+fn id_floor() -> u32 {
+    100_000
+}
+
+fn sudoers_interval() -> u64 {
+    300
+}
+
+fn snapshot_interval() -> u64 {
+    15
+}
+
+fn rate_limit_per_uid() -> u32 {
+    100
+}
+
+fn rate_limit_burst() -> u32 {
+    500
+}
+
+fn max_in_flight_per_uid() -> usize {
+    32
+}
+
+fn max_connections_per_uid() -> usize {
+    256
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -63,8 +216,39 @@ impl Default for Config {
             cache_ttl_hosts: 30,
             cache_ttl_users: 30,
             cache_flush_interval: 900,
+            cache_max_entries: cache_max_entries(),
+            cache_max_negative_entries: cache_max_negative_entries(),
+            cache_max_bytes: cache_max_bytes(),
+            prefetch_interval: prefetch_interval(),
+            prefetch_max_entries: prefetch_max_entries(),
             health_check_interval_healthy: 30,
             health_check_interval_unhealthy: 3,
+            id_floor: id_floor(),
+            reserved_id_ranges: Vec::new(),
+            data_path: data_path(),
+            home_cleanup_enable: false,
+            home_cleanup_dry_run: true,
+            home_cleanup_action: HomeCleanupAction::default(),
+            home_cleanup_grace_days: home_cleanup_grace_days(),
+            home_cleanup_interval: home_cleanup_interval(),
+            home_cleanup_archive_path: home_cleanup_archive_path(),
+            sudoers_enable: false,
+            sudoers_interval: sudoers_interval(),
+            userdb_enable: false,
+            snapshot_enable: true,
+            snapshot_interval: snapshot_interval(),
+            rate_limit_per_uid: rate_limit_per_uid(),
+            rate_limit_burst: rate_limit_burst(),
+            max_in_flight_per_uid: max_in_flight_per_uid(),
+            max_connections_per_uid: max_connections_per_uid(),
+            restrict_enumeration: false,
+            enumeration_group: None,
+            events_enable: false,
+            events_terminate_sessions: false,
+            session_validate_enable: false,
+            session_validate_interval: session_validate_interval(),
+            session_terminate_grace: session_terminate_grace(),
+            session_terminate_message: session_terminate_message(),
         }
     }
 }
@@ -116,6 +300,28 @@ impl Config {
         Ok(())
     }
 
+    /// Sets a minimal config with all defaults, for tests which need the global `Config`.
+    #[cfg(test)]
+    pub fn init_test() {
+        let slf = toml::from_str::<Self>(
+            r#"
+            rauthy_url = 'http://localhost:8080/'
+            host_id = 'test'
+            host_secret = 'secret'
+            log_target = 'console'
+            workers = 1
+            cache_ttl_groups = 30
+            cache_ttl_hosts = 30
+            cache_ttl_users = 30
+            cache_flush_interval = 900
+            health_check_interval_healthy = 30
+            health_check_interval_unhealthy = 3
+            "#,
+        )
+        .unwrap();
+        let _ = CONFIG.set(slf);
+    }
+
     #[inline]
     pub fn read() -> anyhow::Result<Self> {
         let mut file = fs::File::open(CONFIG_PATH)?;
@@ -6,9 +6,12 @@ use std::fmt::{Display, Formatter};
 pub enum ErrorType {
     BadRequest,
     Connection,
+    Forbidden,
     Generic,
     Internal,
     NotFound,
+    TooManyRequests,
+    Unavailable,
 }
 
 #[derive(Debug)]
@@ -45,10 +48,13 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let status = match self.error {
             ErrorType::BadRequest => 400,
-            ErrorType::Connection => 500,
+            ErrorType::Connection => 503,
+            ErrorType::Forbidden => 403,
             ErrorType::Generic => 400,
             ErrorType::Internal => 500,
             ErrorType::NotFound => 404,
+            ErrorType::TooManyRequests => 429,
+            ErrorType::Unavailable => 500,
         };
 
         Response::builder()
@@ -65,6 +71,12 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<rauthy_nss_proto::ProtoError> for Error {
+    fn from(value: rauthy_nss_proto::ProtoError) -> Self {
+        Error::new(ErrorType::BadRequest, value.to_string())
+    }
+}
+
 impl From<std::fmt::Error> for Error {
     fn from(value: std::fmt::Error) -> Self {
         Error::new(ErrorType::Internal, value.to_string())
@@ -100,3 +112,9 @@ impl From<reqwest::Error> for Error {
         )
     }
 }
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::new(ErrorType::BadRequest, format!("Invalid JSON: {value}"))
+    }
+}
@@ -0,0 +1,288 @@
+use crate::api_types::{HostEventsRequest, RauthyEvent};
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::error::{Error, ErrorType};
+use crate::http_client::HttpClient;
+use crate::{session_watch, snapshot};
+use log::{debug, error, info, warn};
+use std::time::Duration;
+use tokio::{task, time};
+
+/// Rauthy sends a keep-alive comment at least every 30 seconds. Anything longer means the
+/// connection is dead, even if TCP did not notice yet.
+const READ_TIMEOUT: Duration = Duration::from_secs(90);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+pub fn spawn() {
+    if !Config::get().events_enable {
+        debug!("Rauthy event subscription is disabled");
+        return;
+    }
+
+    task::spawn(async {
+        let client = match HttpClient::builder().read_timeout(READ_TIMEOUT).build() {
+            Ok(c) => c,
+            Err(err) => {
+                error!("Cannot build the event stream client: {err}");
+                return;
+            }
+        };
+
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let config = Config::get();
+            let res = subscribe(
+                &client,
+                config.rauthy_url.as_str(),
+                &config.host_id,
+                &config.host_secret,
+            )
+            .await;
+            match res {
+                Ok(()) => {
+                    info!("Rauthy event stream closed - re-connecting");
+                    backoff = Duration::from_secs(1);
+                }
+                Err(err) => {
+                    warn!("Rauthy event stream error: {err} - re-connecting in {backoff:?}");
+                    time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    });
+}
+
+async fn subscribe(
+    client: &reqwest::Client,
+    rauthy_url: &str,
+    host_id: &str,
+    host_secret: &str,
+) -> Result<(), Error> {
+    let url = format!("{rauthy_url}auth/v1/pam/hosts/{host_id}/events");
+
+    let mut res = client
+        .post(url)
+        .header("accept", "text/event-stream")
+        .json(&HostEventsRequest { host_secret })
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(Error::new(
+            ErrorType::Connection,
+            format!("Rauthy returned {} for the event stream", res.status()),
+        ));
+    }
+
+    // We may have missed any amount of events while we were disconnected.
+    info!("Subscribed to Rauthy events");
+    Cache::clear().await;
+    snapshot::rebuild();
+
+    let mut parser = SseParser::default();
+    while let Some(chunk) = res.chunk().await? {
+        for data in parser.feed(&chunk) {
+            match serde_json::from_str::<RauthyEvent>(&data) {
+                Ok(event) => handle(event).await,
+                Err(err) => {
+                    warn!("Unknown Rauthy event '{data}': {err}");
+                    handle(RauthyEvent::Reset).await;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle(event: RauthyEvent) {
+    info!("Rauthy event: {event:?}");
+
+    match cache_keys(&event) {
+        Some(keys) => Cache::remove(keys).await,
+        None => Cache::clear().await,
+    }
+    snapshot::rebuild();
+
+    // The uid comes from Rauthy and is only trusted for users, which currently have a Rauthy
+    // session on this host. It must never be able to hit a local account.
+    if Config::get().events_terminate_sessions
+        && let RauthyEvent::UserDisabled { uid, .. } | RauthyEvent::UserDeleted { uid, .. } = event
+        && session_watch::is_active(uid)
+    {
+        session_watch::terminate(uid);
+    }
+}
+
+/// The cache keys affected by an event, or `None` if the whole cache must be cleared.
+fn cache_keys(event: &RauthyEvent) -> Option<Vec<String>> {
+    let keys = match event {
+        RauthyEvent::UserChanged { name, uid }
+        | RauthyEvent::UserDisabled { name, uid }
+        | RauthyEvent::UserDeleted { name, uid } => vec![
+            format!("u_{name}"),
+            format!("u_{uid}"),
+            "$users$".to_string(),
+            // group members and netgroups contain the username
+            "$groups$".to_string(),
+            "$netgroups$".to_string(),
+        ],
+        RauthyEvent::GroupChanged { name, gid } => vec![
+            format!("g_{name}"),
+            format!("g_{gid}"),
+            "$groups$".to_string(),
+            "$netgroups$".to_string(),
+        ],
+        // Hosts are indexed by names, aliases and IPs, which the event does not contain.
+        RauthyEvent::HostsChanged | RauthyEvent::Reset => return None,
+    };
+    Some(keys)
+}
+
+/// A minimal parser for `text/event-stream`, which only cares about `data` fields.
+#[derive(Debug, Default)]
+struct SseParser {
+    buf: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Returns the `data` of all events, that have been completed with this chunk.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line = self.buf.drain(..=pos).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if line.is_empty() {
+                // an empty line dispatches the event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data
+                    .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+            // comments (`: keep-alive`), `event`, `id` and `retry` are ignored
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parse_split_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b": keep-alive\n\nda").is_empty());
+        assert!(parser.feed(b"ta: {\"typ\":\"reset\"}\r").is_empty());
+        assert_eq!(parser.feed(b"\n\r\n"), vec![r#"{"typ":"reset"}"#]);
+
+        let events = parser.feed(b"event: x\ndata: a\ndata: b\n\ndata: c\n\n");
+        assert_eq!(events, vec!["a\nb", "c"]);
+    }
+
+    #[test]
+    fn keys_for_events() {
+        let keys = cache_keys(&RauthyEvent::UserDisabled {
+            name: "alice".to_string(),
+            uid: 100_001,
+        })
+        .unwrap();
+        assert!(keys.contains(&"u_alice".to_string()));
+        assert!(keys.contains(&"u_100001".to_string()));
+        assert!(keys.contains(&"$groups$".to_string()));
+
+        assert!(cache_keys(&RauthyEvent::HostsChanged).is_none());
+    }
+
+    /// Runs `subscribe()` against a local stand-in for Rauthy, which sends the events in
+    /// small chunks with keep-alives in between, and checks the evicted cache entries.
+    #[tokio::test]
+    async fn stream_from_stand_in_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (ready_tx, ready_rx) = tokio::sync::oneshot::channel::<()>();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            let req = String::from_utf8_lossy(&buf[..n]).to_string();
+
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\
+                    transfer-encoding: chunked\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            stream.flush().await.unwrap();
+            ready_rx.await.unwrap();
+
+            for part in [
+                ": keep-alive\n\n",
+                "data: {\"typ\":\"user_disabled\",",
+                "\"name\":\"alice\",\"uid\":100001}\n\n",
+                ": keep-alive\n\n",
+                "data: {\"typ\":\"group_changed\",\"name\":\"admins\",\"gid\":100500}\n\n",
+            ] {
+                let chunk = format!("{:x}\r\n{part}\r\n", part.len());
+                stream.write_all(chunk.as_bytes()).await.unwrap();
+                stream.flush().await.unwrap();
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            stream.write_all(b"0\r\n\r\n").await.unwrap();
+            req
+        });
+
+        Config::init_test();
+        Cache::init();
+        let keys = [
+            "u_alice", "u_100001", "g_admins", "g_100500", "$groups$", "u_bob",
+        ];
+        Cache::set("probe".to_string(), Some(vec![1]), 60).await;
+
+        let client = tokio::spawn(async move {
+            subscribe(
+                &reqwest::Client::new(),
+                &format!("http://{addr}/"),
+                "test",
+                "secret",
+            )
+            .await
+        });
+
+        // the cache is cleared once after connecting, only then the entries can be evicted
+        while Cache::get("probe".to_string()).await.is_some() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        for key in keys {
+            Cache::set(key.to_string(), Some(vec![1]), 60).await;
+        }
+        ready_tx.send(()).unwrap();
+
+        client.await.unwrap().unwrap();
+        for key in &keys[..5] {
+            assert!(
+                Cache::get(key.to_string()).await.is_none(),
+                "{key} was not evicted"
+            );
+        }
+        assert_eq!(Cache::get("u_bob".to_string()).await, Some(Some(vec![1])));
+
+        let req = server.await.unwrap();
+        assert!(req.starts_with("POST /auth/v1/pam/hosts/test/events "));
+        assert!(req.contains("accept: text/event-stream"));
+        assert!(req.contains(r#""host_secret":"secret""#));
+    }
+}
@@ -1,11 +1,14 @@
 use crate::api_types::Getent;
 use crate::handler::{ApiResponse, fetch_getent};
-use axum::extract::Path;
+use crate::peer::Peer;
+use axum::extract::{ConnectInfo, Path};
 use log::info;
 use std::net::IpAddr;
 use tokio::time::Instant;
 
-pub async fn get_hosts() -> ApiResponse {
+pub async fn get_hosts(ConnectInfo(peer): ConnectInfo<Peer>) -> ApiResponse {
+    peer.check_getent(&Getent::Hosts).await?;
+
     let start = Instant::now();
     match fetch_getent(Getent::Hosts).await {
         Ok(res) => {
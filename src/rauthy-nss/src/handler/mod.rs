@@ -1,10 +1,11 @@
 use crate::api_types::{Getent, GetentResponse, GroupType};
 use crate::cache::Cache;
+use crate::collisions::LocalIds;
 use crate::config::Config;
 use crate::error::{Error, ErrorType};
 use crate::groups_local::GroupLocal;
 use crate::http_client::HttpClient;
-use crate::utils::serialize;
+use crate::utils::{deserialize, serialize};
 use crate::{RAUTHY_HEALTHY, VERSION};
 use axum::body::Body;
 use axum::http::Response;
@@ -13,66 +14,48 @@ use std::sync::atomic::Ordering;
 
 pub mod groups;
 pub mod hosts;
+pub mod netgroups;
+pub mod sessions;
+pub mod stats;
+pub mod sudoers;
 pub mod users;
+pub mod whoami;
 
 pub type ApiResponse = Result<Response<Body>, Error>;
 
 static CACHE_KEY_USERS: &str = "$users$";
 static CACHE_KEY_GROUPS: &str = "$groups$";
 static CACHE_KEY_HOSTS: &str = "$hosts$";
+static CACHE_KEY_NETGROUPS: &str = "$netgroups$";
 
 pub async fn get_root() -> String {
     format!("Rauthy NSS Proxy v{VERSION}")
 }
 
 async fn fetch_getent(getent: Getent) -> ApiResponse {
-    let (cached, cache_key) = match &getent {
-        Getent::Users => {
-            let cached = Cache::get(CACHE_KEY_USERS.to_string()).await;
-            (cached, CACHE_KEY_USERS.to_string())
-        }
-        Getent::Username(username) => {
-            let key = format!("u_{username}");
-            let cached = Cache::get(key.clone()).await;
-            (cached, key)
-        }
-        Getent::UserId(uid) => {
-            let key = format!("u_{uid}");
-            let cached = Cache::get(key.clone()).await;
-            (cached, key)
-        }
-        Getent::Groups => {
-            let cached = Cache::get(CACHE_KEY_GROUPS.to_string()).await;
-            (cached, CACHE_KEY_GROUPS.to_string())
-        }
-        Getent::Groupname(groupname) => {
-            let key = format!("g_{groupname}");
-            let cached = Cache::get(key.to_string()).await;
-            (cached, key)
-        }
-        Getent::GroupId(gid) => {
-            let key = format!("g_{gid}");
-            let cached = Cache::get(key.to_string()).await;
-            (cached, key)
-        }
-        Getent::Hosts => {
-            let cached = Cache::get(CACHE_KEY_HOSTS.to_string()).await;
-            (cached, CACHE_KEY_HOSTS.to_string())
-        }
-        Getent::Hostname(hostname) => {
-            let key = format!("h_{hostname}");
-            let cached = Cache::get(key.to_string()).await;
-            (cached, key)
-        }
-        Getent::HostIp(ip) => {
-            let key = format!("h_{ip}");
-            let cached = Cache::get(key.to_string()).await;
-            (cached, key)
-        }
-    };
+    let bytes = getent_bytes(getent).await?;
+    Ok(Response::builder()
+        .status(200)
+        .body(Body::from(bytes))
+        .unwrap())
+}
 
-    if let Some(opt) = cached {
+/// Returns the serialized `GetentResponse` either from cache or freshly fetched from Rauthy.
+/// Values that do not exist or collide with local ones are returned as `NotFound`, while
+/// `Connection` means they cannot be resolved right now.
+pub async fn getent_bytes(getent: Getent) -> Result<Vec<u8>, Error> {
+    let cache_key = cache_key(&getent);
+
+    let mut cached = Cache::get(cache_key.clone()).await;
+    if cached.is_some() {
         debug!("Cache hit");
+    } else {
+        cached = from_listing(&getent, &cache_key).await;
+        if cached.is_some() {
+            debug!("Resolved from cached listing");
+        }
+    }
+    if let Some(opt) = cached {
         return match opt {
             None => {
                 // we do this check for negative caching
@@ -81,10 +64,7 @@ async fn fetch_getent(getent: Getent) -> ApiResponse {
                     format!("Negative cache value: {getent:?}"),
                 ))
             }
-            Some(value) => Ok(Response::builder()
-                .status(200)
-                .body(Body::from(value))
-                .unwrap()),
+            Some(value) => Ok(value),
         };
     }
 
@@ -93,31 +73,40 @@ async fn fetch_getent(getent: Getent) -> ApiResponse {
         // During startup for instance, we try to resolve our own target
         // address via the systems hosts, for which we should provide the data.
         // If the connection to Rauthy is unhealthy or Rauthy itself is unhealthy,
-        // just send back NotFound.
+        // fail fast, so the next NSS service can take over.
         return Err(Error::new(
-            ErrorType::NotFound,
+            ErrorType::Connection,
             format!("Rauthy unhealthy: {getent:?}"),
         ));
     }
 
-    let config = Config::get();
+    fetch_and_cache(getent, cache_key).await
+}
+
+/// Always fetches `getent` from Rauthy and updates the cache, even if it has a valid value.
+pub async fn refresh(getent: Getent) -> Result<Vec<u8>, Error> {
+    let cache_key = cache_key(&getent);
+    fetch_and_cache(getent, cache_key).await
+}
+
+async fn fetch_and_cache(getent: Getent, cache_key: String) -> Result<Vec<u8>, Error> {
+    let ttl = cache_ttl(&getent);
 
     let resp = match HttpClient::getent(&getent).await {
         Ok(r) => r,
         Err(err) => {
+            // Never cache this as "not found". The next lookup must try again.
             error!("Rauthy Connection Error: {err:?}");
-            Cache::set(cache_key, None, 10).await;
-            return Err(Error::new(
-                ErrorType::NotFound,
-                format!("value not found: {getent:?}"),
-            ));
+            return Err(err);
         }
     };
 
     let bytes = if let Some(resp) = resp {
         let resp = match resp {
-            GetentResponse::Users(r) => GetentResponse::Users(r),
-            GetentResponse::User(r) => GetentResponse::User(r),
+            GetentResponse::Users(r) => {
+                Some(GetentResponse::Users(LocalIds::filter_users(r).await?))
+            }
+            GetentResponse::User(r) => LocalIds::filter_user(r).await?.map(GetentResponse::User),
             GetentResponse::Groups(mut groups) => {
                 if let Some(locals) = GroupLocal::read().await? {
                     for group in groups.iter_mut() {
@@ -137,7 +126,9 @@ async fn fetch_getent(getent: Getent) -> ApiResponse {
                         }
                     }
                 }
-                GetentResponse::Groups(groups)
+                Some(GetentResponse::Groups(
+                    LocalIds::filter_groups(groups).await?,
+                ))
             }
             GetentResponse::Group(mut group) => {
                 if group.typ == GroupType::Local
@@ -145,22 +136,26 @@ async fn fetch_getent(getent: Getent) -> ApiResponse {
                 {
                     group.id = local.id;
                 }
-                GetentResponse::Group(group)
+                LocalIds::filter_group(group)
+                    .await?
+                    .map(GetentResponse::Group)
             }
-            GetentResponse::Hosts(g) => GetentResponse::Hosts(g),
-            GetentResponse::Host(g) => GetentResponse::Host(g),
+            GetentResponse::Hosts(g) => Some(GetentResponse::Hosts(g)),
+            GetentResponse::Host(g) => Some(GetentResponse::Host(g)),
+            GetentResponse::Netgroups(n) => Some(GetentResponse::Netgroups(n)),
+            GetentResponse::Netgroup(n) => Some(GetentResponse::Netgroup(n)),
         };
 
-        Some(serialize(&resp)?)
+        if let Some(resp) = &resp {
+            index_listing(resp, ttl).await?;
+        }
+
+        // `None` if the value collides with a local user or group
+        resp.map(|r| serialize(&r)).transpose()?
     } else {
         None
     };
 
-    let ttl = match getent {
-        Getent::Users | Getent::UserId(_) | Getent::Username(_) => config.cache_ttl_users,
-        Getent::Groups | Getent::GroupId(_) | Getent::Groupname(_) => config.cache_ttl_groups,
-        Getent::Hosts | Getent::Hostname(_) | Getent::HostIp(_) => config.cache_ttl_hosts,
-    };
     Cache::set(cache_key, bytes.clone(), ttl).await;
 
     match bytes {
@@ -168,9 +163,134 @@ async fn fetch_getent(getent: Getent) -> ApiResponse {
             ErrorType::NotFound,
             format!("value not found: {getent:?}"),
         )),
-        Some(value) => Ok(Response::builder()
-            .status(200)
-            .body(Body::from(value))
-            .unwrap()),
+        Some(value) => Ok(value),
     }
 }
+
+fn cache_key(getent: &Getent) -> String {
+    match getent {
+        Getent::Users => CACHE_KEY_USERS.to_string(),
+        Getent::Username(username) => format!("u_{username}"),
+        Getent::UserId(uid) => format!("u_{uid}"),
+        Getent::Groups => CACHE_KEY_GROUPS.to_string(),
+        Getent::Groupname(groupname) => format!("g_{groupname}"),
+        Getent::GroupId(gid) => format!("g_{gid}"),
+        Getent::Hosts => CACHE_KEY_HOSTS.to_string(),
+        Getent::Hostname(hostname) => format!("h_{hostname}"),
+        Getent::HostIp(ip) => format!("h_{ip}"),
+        Getent::Netgroups => CACHE_KEY_NETGROUPS.to_string(),
+        Getent::Netgroupname(name) => format!("n_{name}"),
+    }
+}
+
+fn cache_ttl(getent: &Getent) -> u32 {
+    let config = Config::get();
+    match getent {
+        Getent::Users | Getent::UserId(_) | Getent::Username(_) => config.cache_ttl_users,
+        Getent::Groups | Getent::GroupId(_) | Getent::Groupname(_) => config.cache_ttl_groups,
+        // netgroups are derived from host groups and their members
+        Getent::Netgroups | Getent::Netgroupname(_) => config.cache_ttl_groups,
+        Getent::Hosts | Getent::Hostname(_) | Getent::HostIp(_) => config.cache_ttl_hosts,
+    }
+}
+
+/// Caches each entry of a full listing under the keys of its single lookups, so that name
+/// and id lookups are answered without another request and stay consistent with the
+/// listing. Single responses are ignored.
+async fn index_listing(resp: &GetentResponse, ttl: u32) -> Result<(), Error> {
+    let mut entries = Vec::new();
+
+    match resp {
+        GetentResponse::Users(users) => {
+            for user in users {
+                let value = serialize(&GetentResponse::User(user.clone()))?;
+                entries.push((format!("u_{}", user.id), Some(value.clone())));
+                entries.push((format!("u_{}", user.name), Some(value)));
+            }
+        }
+        GetentResponse::Groups(groups) => {
+            for group in groups {
+                let value = serialize(&GetentResponse::Group(group.clone()))?;
+                entries.push((format!("g_{}", group.id), Some(value.clone())));
+                entries.push((format!("g_{}", group.name), Some(value)));
+            }
+        }
+        GetentResponse::Hosts(hosts) => {
+            for host in hosts {
+                let value = serialize(&GetentResponse::Host(host.clone()))?;
+                for name in std::iter::once(&host.name).chain(host.aliases.iter()) {
+                    entries.push((format!("h_{name}"), Some(value.clone())));
+                }
+                for ip in &host.addresses {
+                    entries.push((format!("h_{ip}"), Some(value.clone())));
+                }
+            }
+        }
+        GetentResponse::Netgroups(netgroups) => {
+            for ng in netgroups {
+                let value = serialize(&GetentResponse::Netgroup(ng.clone()))?;
+                entries.push((format!("n_{}", ng.name), Some(value)));
+            }
+        }
+        _ => return Ok(()),
+    }
+
+    // Huge listings would push everything else out of the cache. `from_listing()` still
+    // answers single lookups from them.
+    if entries.len() > Config::get().cache_max_entries / 2 {
+        debug!("Listing too large for indexing with {} keys", entries.len());
+        return Ok(());
+    }
+    Cache::set_many(entries, ttl).await;
+    Ok(())
+}
+
+/// Answers a single lookup from a cached full listing, in case its own key has been
+/// evicted or was never indexed. A value missing from the listing does not exist.
+async fn from_listing(getent: &Getent, cache_key: &str) -> Option<Option<Vec<u8>>> {
+    let listing_key = match getent {
+        Getent::Username(_) | Getent::UserId(_) => CACHE_KEY_USERS,
+        Getent::Groupname(_) | Getent::GroupId(_) => CACHE_KEY_GROUPS,
+        Getent::Hostname(_) | Getent::HostIp(_) => CACHE_KEY_HOSTS,
+        Getent::Netgroupname(_) => CACHE_KEY_NETGROUPS,
+        Getent::Users | Getent::Groups | Getent::Hosts | Getent::Netgroups => return None,
+    };
+    let listing = Cache::get(listing_key.to_string()).await??;
+    let listing = deserialize::<GetentResponse>(&listing).ok()?;
+
+    let resp = match (getent, listing) {
+        (Getent::Username(name), GetentResponse::Users(users)) => users
+            .into_iter()
+            .find(|u| &u.name == name)
+            .map(GetentResponse::User),
+        (Getent::UserId(uid), GetentResponse::Users(users)) => users
+            .into_iter()
+            .find(|u| u.id == *uid)
+            .map(GetentResponse::User),
+        (Getent::Groupname(name), GetentResponse::Groups(groups)) => groups
+            .into_iter()
+            .find(|g| &g.name == name)
+            .map(GetentResponse::Group),
+        (Getent::GroupId(gid), GetentResponse::Groups(groups)) => groups
+            .into_iter()
+            .find(|g| g.id == *gid)
+            .map(GetentResponse::Group),
+        (Getent::Hostname(name), GetentResponse::Hosts(hosts)) => hosts
+            .into_iter()
+            .find(|h| &h.name == name || h.aliases.contains(name))
+            .map(GetentResponse::Host),
+        (Getent::HostIp(ip), GetentResponse::Hosts(hosts)) => hosts
+            .into_iter()
+            .find(|h| h.addresses.contains(ip))
+            .map(GetentResponse::Host),
+        (Getent::Netgroupname(name), GetentResponse::Netgroups(netgroups)) => netgroups
+            .into_iter()
+            .find(|n| &n.name == name)
+            .map(GetentResponse::Netgroup),
+        _ => return None,
+    };
+
+    let value = resp.map(|r| serialize(&r)).transpose().ok()?;
+    Cache::set(cache_key.to_string(), value.clone(), cache_ttl(getent)).await;
+    Some(value)
+}
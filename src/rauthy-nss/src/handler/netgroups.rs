@@ -0,0 +1,45 @@
+use crate::api_types::Getent;
+use crate::handler::{ApiResponse, fetch_getent};
+use crate::peer::Peer;
+use axum::extract::{ConnectInfo, Path};
+use log::info;
+use tokio::time::Instant;
+
+pub async fn get_netgroups(ConnectInfo(peer): ConnectInfo<Peer>) -> ApiResponse {
+    peer.check_getent(&Getent::Netgroups).await?;
+
+    let start = Instant::now();
+    match fetch_getent(Getent::Netgroups).await {
+        Ok(res) => {
+            info!(
+                "get all netgroups - SUCCESS {} µs",
+                start.elapsed().as_micros()
+            );
+            Ok(res)
+        }
+        Err(err) => {
+            info!(
+                "get all netgroups - FAIL {} µs",
+                start.elapsed().as_micros()
+            );
+            Err(err)
+        }
+    }
+}
+
+pub async fn get_netgroup_by_name(Path(name): Path<String>) -> ApiResponse {
+    let start = Instant::now();
+    match fetch_getent(Getent::Netgroupname(name.clone())).await {
+        Ok(res) => {
+            info!(
+                "netgroup {name} - SUCCESS {} µs",
+                start.elapsed().as_micros()
+            );
+            Ok(res)
+        }
+        Err(err) => {
+            info!("netgroup {name} - FAIL {} µs", start.elapsed().as_micros());
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::error::{Error, ErrorType};
+use crate::handler::ApiResponse;
+use crate::peer::Peer;
+use crate::session_watch::{self, SessionOpenRequest};
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Response;
+
+/// Called by the PAM module from `open_session`. The session is identified by the PID of
+/// the connecting process, which is the same one for `close_session`.
+pub async fn post_session_open(ConnectInfo(peer): ConnectInfo<Peer>, body: String) -> ApiResponse {
+    let pid = check_peer(&peer)?;
+    session_watch::open(SessionOpenRequest::parse(&body)?, pid);
+    Ok(Response::builder().status(200).body(Body::empty()).unwrap())
+}
+
+/// Called by the PAM module from `close_session`.
+pub async fn post_session_close(ConnectInfo(peer): ConnectInfo<Peer>) -> ApiResponse {
+    let pid = check_peer(&peer)?;
+    session_watch::close(pid);
+    Ok(Response::builder().status(200).body(Body::empty()).unwrap())
+}
+
+/// The socket is world-writable, but only `root` can run `open_session`, and nobody else
+/// must be able to register tokens or to close sessions.
+fn check_peer(peer: &Peer) -> Result<i32, Error> {
+    if peer.uid != 0 {
+        return Err(Error::new(
+            ErrorType::Forbidden,
+            "only root can register sessions",
+        ));
+    }
+    peer.pid
+        .ok_or_else(|| Error::new(ErrorType::BadRequest, "cannot read the peer pid"))
+}
@@ -0,0 +1,18 @@
+use crate::cache::Cache;
+use crate::error::{Error, ErrorType};
+use crate::handler::ApiResponse;
+use axum::body::Body;
+use axum::http::Response;
+
+/// Cache stats as JSON for monitoring, e.g. `curl --unix-socket <socket> localhost/stats`.
+pub async fn get_stats() -> ApiResponse {
+    let Some(stats) = Cache::stats().await else {
+        return Err(Error::new(ErrorType::Internal, "Cache is not running"));
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&stats)?))
+        .unwrap())
+}
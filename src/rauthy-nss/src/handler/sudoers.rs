@@ -0,0 +1,24 @@
+use crate::handler::ApiResponse;
+use crate::sudoers;
+use axum::body::Body;
+use axum::http::Response;
+use log::info;
+use tokio::time::Instant;
+
+pub async fn get_sudoers() -> ApiResponse {
+    let start = Instant::now();
+    match sudoers::rendered().await {
+        Ok(content) => {
+            info!("get sudoers - SUCCESS {} µs", start.elapsed().as_micros());
+            Ok(Response::builder()
+                .status(200)
+                .header("content-type", "text/plain")
+                .body(Body::from(content))
+                .unwrap())
+        }
+        Err(err) => {
+            info!("get sudoers - FAIL {} µs", start.elapsed().as_micros());
+            Err(err)
+        }
+    }
+}
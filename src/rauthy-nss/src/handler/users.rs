@@ -1,10 +1,13 @@
 use crate::api_types::Getent;
 use crate::handler::{ApiResponse, fetch_getent};
-use axum::extract::Path;
+use crate::peer::Peer;
+use axum::extract::{ConnectInfo, Path};
 use log::info;
 use tokio::time::Instant;
 
-pub async fn get_users() -> ApiResponse {
+pub async fn get_users(ConnectInfo(peer): ConnectInfo<Peer>) -> ApiResponse {
+    peer.check_getent(&Getent::Users).await?;
+
     let start = Instant::now();
     match fetch_getent(Getent::Users).await {
         Ok(res) => {
@@ -0,0 +1,24 @@
+use crate::handler::ApiResponse;
+use crate::whoami;
+use axum::body::Body;
+use axum::http::Response;
+use log::info;
+use tokio::time::Instant;
+
+pub async fn get_whoami() -> ApiResponse {
+    let start = Instant::now();
+    match whoami::public().await {
+        Ok(content) => {
+            info!("get whoami - SUCCESS {} µs", start.elapsed().as_micros());
+            Ok(Response::builder()
+                .status(200)
+                .header("content-type", "text/plain")
+                .body(Body::from(content))
+                .unwrap())
+        }
+        Err(err) => {
+            info!("get whoami - FAIL {} µs", start.elapsed().as_micros());
+            Err(err)
+        }
+    }
+}
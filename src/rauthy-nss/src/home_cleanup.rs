@@ -0,0 +1,312 @@
+use crate::RAUTHY_HEALTHY;
+use crate::api_types::{Getent, GetentResponse};
+use crate::config::{Config, HomeCleanupAction};
+use crate::error::{Error, ErrorType};
+use crate::http_client::HttpClient;
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::Permissions;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::{fs, task, time};
+
+static HOME_PATH: &str = "/home";
+static AUDIT_LOG: &str = "/var/log/rauthy/home_cleanup.log";
+/// Remembers when each orphaned dir was seen for the first time, so the grace period
+/// survives restarts.
+static STATE_FILE: &str = "home_cleanup.toml";
+
+pub fn spawn() {
+    let config = Config::get();
+    if !config.home_cleanup_enable {
+        debug!("home dir cleanup is disabled");
+        return;
+    }
+    if config.home_cleanup_dry_run {
+        info!("home dir cleanup is running in dry-run mode");
+    }
+
+    task::spawn(async {
+        let mut interval = time::interval(Duration::from_secs(Config::get().home_cleanup_interval));
+        loop {
+            interval.tick().await;
+
+            if !RAUTHY_HEALTHY.load(Ordering::Relaxed) {
+                warn!("Rauthy is unhealthy - skipping home dir cleanup");
+                continue;
+            }
+            if let Err(err) = reconcile().await {
+                error!("Home dir cleanup error: {err}");
+            }
+        }
+    });
+}
+
+async fn reconcile() -> Result<(), Error> {
+    let config = Config::get();
+
+    // Never use cached data here. An empty list would mean every single home dir is orphaned,
+    // which is much more likely a misconfiguration than reality.
+    let users = match HttpClient::getent(&Getent::Users).await? {
+        Some(GetentResponse::Users(users)) if !users.is_empty() => users,
+        _ => {
+            return Err(Error::new(
+                ErrorType::Connection,
+                "Cannot fetch users from Rauthy or the list is empty",
+            ));
+        }
+    };
+    let names = users
+        .iter()
+        .map(|u| u.name.as_str())
+        .collect::<HashSet<_>>();
+    let uids = users.iter().map(|u| u.id).collect::<HashSet<_>>();
+    let (local_names, local_uids) = local_users().await?;
+
+    let mut orphans = Vec::new();
+    let mut entries = fs::read_dir(HOME_PATH).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        // does not follow symlinks
+        let meta = entry.metadata().await?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !meta.is_dir()
+            || meta.uid() < config.id_floor
+            || local_names.contains(&name)
+            || local_uids.contains(&meta.uid())
+            || names.contains(name.as_str())
+            || uids.contains(&meta.uid())
+        {
+            continue;
+        }
+        orphans.push(entry.path());
+    }
+
+    // the PAM data dirs only ever contain short-lived tokens
+    if let Ok(mut entries) = fs::read_dir(&config.data_path).await {
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !names.contains(name.as_str()) && is_token_dir(&entry.path()).await {
+                orphans.push(entry.path());
+            }
+        }
+    }
+
+    let now = Utc::now().timestamp();
+    let grace = config.home_cleanup_grace_days as i64 * 86400;
+    let mut state = load_state().await;
+    // users may have been re-created in the meantime
+    state.retain(|path, _| orphans.contains(path));
+
+    for path in orphans {
+        let first_seen = match state.get(&path) {
+            Some(ts) => *ts,
+            None => {
+                audit("DETECTED", &path, "orphaned, grace period started").await;
+                state.insert(path.clone(), now);
+                now
+            }
+        };
+        if now - first_seen < grace {
+            continue;
+        }
+
+        let is_home = path.starts_with(HOME_PATH);
+        let action = if is_home {
+            &config.home_cleanup_action
+        } else {
+            &HomeCleanupAction::Delete
+        };
+
+        if config.home_cleanup_dry_run {
+            audit("DRY-RUN", &path, &format!("would {action:?}")).await;
+            continue;
+        }
+
+        let res = if !is_home {
+            remove_token_dir(&path).await
+        } else {
+            match action {
+                HomeCleanupAction::Archive => archive(&path).await,
+                HomeCleanupAction::Delete => fs::remove_dir_all(&path)
+                    .await
+                    .map(|_| "deleted".to_string())
+                    .map_err(Error::from),
+            }
+        };
+        match res {
+            Ok(detail) => {
+                audit(&format!("{action:?}").to_uppercase(), &path, &detail).await;
+                state.remove(&path);
+            }
+            Err(err) => {
+                audit("FAILED", &path, &err.to_string()).await;
+            }
+        }
+    }
+
+    save_state(&state).await
+}
+
+async fn archive(path: &Path) -> Result<String, Error> {
+    let config = Config::get();
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(Error::new(
+            ErrorType::Internal,
+            format!("Invalid home dir path {path:?}"),
+        ));
+    };
+
+    fs::create_dir_all(&config.home_cleanup_archive_path).await?;
+    fs::set_permissions(
+        &config.home_cleanup_archive_path,
+        Permissions::from_mode(0o700),
+    )
+    .await?;
+
+    let target = config.home_cleanup_archive_path.join(format!(
+        "{}-{}.tar.gz",
+        name.to_string_lossy(),
+        Utc::now().format("%Y%m%d%H%M%S")
+    ));
+
+    let out = Command::new("/usr/bin/tar")
+        .arg("--create")
+        .arg("--gzip")
+        .arg("--file")
+        .arg(&target)
+        .arg("-C")
+        .arg(parent)
+        .arg(name)
+        .output()
+        .await?;
+    if !out.status.success() {
+        let _ = fs::remove_file(&target).await;
+        return Err(Error::new(
+            ErrorType::Internal,
+            format!(
+                "tar failed, keeping {path:?}: {}",
+                String::from_utf8_lossy(&out.stderr)
+            ),
+        ));
+    }
+
+    fs::remove_dir_all(path).await?;
+    Ok(format!("archived to {}", target.display()))
+}
+
+/// `data_path` is shared with other data, like the host key or our own state. Only dirs
+/// which match the token store layout `<data_path>/<username>/token` exactly are ever
+/// considered. Symlinks are never followed.
+async fn is_token_dir(path: &Path) -> bool {
+    if !fs::symlink_metadata(path)
+        .await
+        .is_ok_and(|meta| meta.is_dir())
+    {
+        return false;
+    }
+    let Ok(mut entries) = fs::read_dir(path).await else {
+        return false;
+    };
+
+    let mut is_token = false;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if is_token || entry.file_name() != "token" {
+            return false;
+        }
+        // does not follow symlinks
+        is_token = entry.metadata().await.is_ok_and(|meta| meta.is_file());
+    }
+    is_token
+}
+
+/// Removes an orphaned token dir without ever descending into anything unexpected, since
+/// the dir may have changed during the grace period.
+async fn remove_token_dir(path: &Path) -> Result<String, Error> {
+    if !is_token_dir(path).await {
+        return Err(Error::new(
+            ErrorType::Internal,
+            format!("{path:?} does not match the token store layout anymore - keeping it"),
+        ));
+    }
+    fs::remove_file(path.join("token")).await?;
+    fs::remove_dir(path).await?;
+    Ok("deleted".to_string())
+}
+
+/// Returns all usernames and uids from `/etc/passwd`.
+async fn local_users() -> Result<(HashSet<String>, HashSet<u32>), Error> {
+    let mut names = HashSet::new();
+    let mut uids = HashSet::new();
+
+    for line in fs::read_to_string("/etc/passwd").await?.lines() {
+        let mut parts = line.split(':');
+        if let (Some(name), Some(_x), Some(Ok(uid))) = (
+            parts.next(),
+            parts.next(),
+            parts.next().map(str::parse::<u32>),
+        ) {
+            names.insert(name.to_string());
+            uids.insert(uid);
+        }
+    }
+
+    Ok((names, uids))
+}
+
+async fn audit(action: &str, path: &Path, detail: &str) {
+    let line = format!(
+        "{} {action} {} - {detail}\n",
+        Utc::now().to_rfc3339(),
+        path.display()
+    );
+    info!("home dir cleanup: {}", line.trim_end());
+
+    let res = async {
+        if let Some(parent) = Path::new(AUDIT_LOG).parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(AUDIT_LOG)
+            .await?;
+        file.write_all(line.as_bytes()).await
+    }
+    .await;
+    if let Err(err) = res {
+        error!("Cannot write to {AUDIT_LOG}: {err}");
+    }
+}
+
+async fn load_state() -> BTreeMap<PathBuf, i64> {
+    let path = Config::get().data_path.join(STATE_FILE);
+    match fs::read_to_string(&path).await {
+        Ok(s) => toml::from_str::<BTreeMap<String, i64>>(&s)
+            .map(|m| m.into_iter().map(|(k, v)| (PathBuf::from(k), v)).collect())
+            .unwrap_or_else(|err| {
+                error!("Cannot parse {path:?}, starting from scratch: {err}");
+                BTreeMap::new()
+            }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+async fn save_state(state: &BTreeMap<PathBuf, i64>) -> Result<(), Error> {
+    let path = Config::get().data_path.join(STATE_FILE);
+    let map = state
+        .iter()
+        .map(|(k, v)| (k.display().to_string(), *v))
+        .collect::<BTreeMap<_, _>>();
+    let s =
+        toml::to_string(&map).map_err(|err| Error::new(ErrorType::Internal, err.to_string()))?;
+
+    fs::create_dir_all(&Config::get().data_path).await?;
+    fs::write(&path, s).await?;
+    Ok(())
+}
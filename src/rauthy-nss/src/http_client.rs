@@ -1,27 +1,47 @@
 use crate::VERSION;
 use crate::api_types::{Getent, GetentRequest, GetentResponse};
+use crate::cache::Cache;
 use crate::config::Config;
-use crate::error::Error;
-use log::error;
+use crate::error::{Error, ErrorType};
+use log::{debug, error};
+use reqwest::StatusCode;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
 use reqwest::tls::Version;
-use std::sync::OnceLock;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, OnceLock};
 use std::time::Duration;
 
 static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+/// The `ETag`s of the last full listings received from Rauthy. Listings are only downloaded
+/// again, if they have changed in the meantime, so the traffic scales with the change rate
+/// instead of the amount of users. The listings themselves are kept in the `Cache`, so they
+/// count against its size budget and may be evicted like any other value.
+static ETAGS: LazyLock<Mutex<HashMap<&'static str, String>>> = LazyLock::new(Default::default);
+/// The replica is only ever replaced by a newer listing, or evicted by the `Cache` limits.
+const REPLICA_TTL: u32 = 7 * 24 * 3600;
 
 pub struct HttpClient;
 
 impl HttpClient {
     pub fn init() {
+        let client = Self::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        CLIENT.set(client).unwrap();
+    }
+
+    /// The base config for all clients talking to Rauthy, without a total request timeout.
+    pub fn builder() -> reqwest::ClientBuilder {
         let builder = reqwest::Client::builder()
             .connect_timeout(Duration::from_secs(10))
-            .timeout(Duration::from_secs(10))
             .min_tls_version(Version::TLS_1_2)
             .hickory_dns(true)
             .use_rustls_tls()
             .user_agent(format!("Rauthy NSS Proxy v{VERSION}"));
 
-        let client = if Config::get().danger_allow_insecure {
+        if Config::get().danger_allow_insecure {
             builder
         } else {
             builder
@@ -29,10 +49,6 @@ impl HttpClient {
                 .danger_accept_invalid_certs(false)
                 .danger_accept_invalid_hostnames(false)
         }
-        .build()
-        .unwrap();
-
-        CLIENT.set(client).unwrap();
     }
 
     #[inline]
@@ -51,19 +67,101 @@ impl HttpClient {
             getent,
         };
 
-        let res = match CLIENT.get().unwrap().post(url).json(&payload).send().await {
-            Ok(r) => r,
-            Err(err) => {
-                error!("Error sending request to Rauthy: {err:?}");
-                return Err(Error::from(err));
+        let listing = listing_key(getent);
+        let etag = listing.and_then(|key| ETAGS.lock().unwrap().get(key).cloned());
+
+        let mut res = send_getent(&url, &payload, etag.as_deref()).await?;
+
+        // `412` is the correct answer to a matching `If-None-Match` for a `POST`
+        if let Some(key) = listing
+            && matches!(
+                res.status(),
+                StatusCode::NOT_MODIFIED | StatusCode::PRECONDITION_FAILED
+            )
+        {
+            if let Some(Some(bytes)) = Cache::get(replica_key(key)).await {
+                debug!("Listing {key} not modified");
+                return Ok(Some(serde_json::from_slice(&bytes)?));
             }
-        };
+
+            // the replica has been evicted in the meantime, we need the full listing again
+            debug!("Replica for listing {key} evicted, fetching it again");
+            ETAGS.lock().unwrap().remove(key);
+            res = send_getent(&url, &payload, None).await?;
+        }
 
         if res.status().is_success() {
-            let resp = res.json::<GetentResponse>().await?;
+            let etag = res
+                .headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from);
+            let bytes = res.bytes().await?;
+            let resp = serde_json::from_slice::<GetentResponse>(&bytes)?;
+
+            if let Some(key) = listing {
+                match etag {
+                    Some(etag) => {
+                        Cache::set(replica_key(key), Some(bytes.to_vec()), REPLICA_TTL).await;
+                        ETAGS.lock().unwrap().insert(key, etag);
+                    }
+                    // older Rauthy versions do not send any
+                    None => {
+                        ETAGS.lock().unwrap().remove(key);
+                    }
+                }
+            }
+
             Ok(Some(resp))
         } else {
-            Ok(None)
+            match res.status() {
+                StatusCode::NOT_FOUND => Ok(None),
+                s if s.is_server_error()
+                    || matches!(
+                        s,
+                        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+                    ) =>
+                {
+                    Err(Error::new(
+                        ErrorType::Connection,
+                        format!("Rauthy returned {s} for {getent:?}"),
+                    ))
+                }
+                s => Err(Error::new(
+                    ErrorType::Unavailable,
+                    format!("Rauthy rejected {getent:?} with {s}"),
+                )),
+            }
         }
     }
 }
+
+async fn send_getent(
+    url: &str,
+    payload: &GetentRequest<'_>,
+    etag: Option<&str>,
+) -> Result<reqwest::Response, Error> {
+    let mut req = CLIENT.get().unwrap().post(url).json(payload);
+    if let Some(etag) = etag {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+
+    req.send().await.map_err(|err| {
+        error!("Error sending request to Rauthy: {err:?}");
+        Error::from(err)
+    })
+}
+
+fn replica_key(listing: &str) -> String {
+    format!("$replica${listing}$")
+}
+
+fn listing_key(getent: &Getent) -> Option<&'static str> {
+    match getent {
+        Getent::Users => Some("users"),
+        Getent::Groups => Some("groups"),
+        Getent::Hosts => Some("hosts"),
+        Getent::Netgroups => Some("netgroups"),
+        _ => None,
+    }
+}
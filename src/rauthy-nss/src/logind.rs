@@ -0,0 +1,45 @@
+use crate::error::{Error, ErrorType};
+use log::info;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+/// Searched after `PATH`, which may be minimal or missing for a system service.
+static LOGINCTL_DIRS: [&str; 2] = ["/usr/bin", "/bin"];
+
+/// Terminates all sessions of `uid` via `systemd-logind`, which kills all of its processes.
+pub async fn terminate_user(uid: u32) -> Result<(), Error> {
+    let Some(loginctl) = loginctl() else {
+        return Err(Error::new(
+            ErrorType::Internal,
+            format!("Cannot terminate sessions of uid {uid}: loginctl not found"),
+        ));
+    };
+
+    let out = Command::new(loginctl)
+        .arg("terminate-user")
+        .arg(uid.to_string())
+        .output()
+        .await?;
+
+    if out.status.success() {
+        info!("Terminated all sessions of uid {uid}");
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorType::Internal,
+            format!(
+                "loginctl terminate-user {uid} failed: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ))
+    }
+}
+
+/// Distributions install `loginctl` into `/usr/bin` or `/bin`.
+fn loginctl() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(LOGINCTL_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join("loginctl"))
+        .find(|p| p.is_file())
+}
@@ -5,14 +5,25 @@ use std::sync::atomic::AtomicBool;
 
 mod api_types;
 mod cache;
+mod collisions;
 mod config;
 mod error;
+mod events;
 mod groups_local;
 mod handler;
 mod health_check;
+mod home_cleanup;
 mod http_client;
 mod logging;
+mod logind;
+mod nss_server;
+mod peer;
+mod prefetch;
 mod server;
+mod session_watch;
+mod snapshot;
+mod sudoers;
+mod userdb;
 mod utils;
 mod whoami;
 
@@ -26,6 +37,22 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 static PROXY_SOCKET: &str = "/tmp/rauthy/rauthy_proxy.sock";
 #[cfg(not(debug_assertions))]
 static PROXY_SOCKET: &str = "/run/rauthy/rauthy_proxy.sock";
+// getent lookups from the NSS module, see `rauthy-nss-proto`
+#[cfg(debug_assertions)]
+static NSS_SOCKET: &str = "/tmp/rauthy/rauthy_nss.sock";
+#[cfg(not(debug_assertions))]
+static NSS_SOCKET: &str = "/run/rauthy/rauthy_nss.sock";
+// read-only snapshot for the NSS module, see `rauthy-nss-proto`
+#[cfg(debug_assertions)]
+static SNAPSHOT_PATH: &str = "/tmp/rauthy/nss.snapshot";
+#[cfg(not(debug_assertions))]
+static SNAPSHOT_PATH: &str = "/run/rauthy/nss.snapshot";
+// The NSS module cannot read the config file, so we share the `id_floor` with it through a
+// world-readable file next to the socket.
+#[cfg(debug_assertions)]
+static ID_FLOOR_PATH: &str = "/tmp/rauthy/id_floor";
+#[cfg(not(debug_assertions))]
+static ID_FLOOR_PATH: &str = "/run/rauthy/id_floor";
 
 pub static RAUTHY_HEALTHY: AtomicBool = AtomicBool::new(false);
 
@@ -57,6 +84,14 @@ fn main() -> anyhow::Result<()> {
         if let Err(err) = whoami::whoami().await {
             error!("Whoami error: {err}");
         }
+        sudoers::spawn_generator();
+        home_cleanup::spawn();
+        userdb::spawn();
+        nss_server::spawn();
+        prefetch::spawn();
+        snapshot::spawn();
+        events::spawn();
+        session_watch::spawn();
 
         server::run().await
     })?;
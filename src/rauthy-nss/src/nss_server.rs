@@ -0,0 +1,133 @@
+use crate::NSS_SOCKET;
+use crate::error::{Error, ErrorType};
+use crate::handler::getent_bytes;
+use crate::peer::Peer;
+use log::{debug, error, info};
+use rauthy_nss_proto::{
+    Getent, HEADER_LEN, HELLO_LEN, PROTOCOL_VERSION, ProtoError, Status, decode_request,
+    encode_response, hello, parse_hello, parse_request_header,
+};
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{fs, task, time};
+
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+/// The NSS module sends its hello and each request at once, right after connecting.
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+/// Keep-alive connections are closed after this, and re-opened by the NSS module on demand.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Serves getent lookups for the NSS module with the compact protocol from `rauthy-nss-proto`.
+/// Everything else, like `/whoami` or `/sudoers`, stays on the HTTP socket.
+pub fn spawn() {
+    task::spawn(async {
+        if let Err(err) = run().await {
+            error!("NSS socket error: {err}");
+        }
+    });
+}
+
+async fn run() -> Result<(), Error> {
+    let path = Path::new(NSS_SOCKET);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let _ = fs::remove_file(path).await;
+
+    let listener = UnixListener::bind(path)?;
+    // Same rules as for the HTTP socket: every process on this host must be able to resolve
+    // users, while the parent dir protects it from being replaced.
+    fs::set_permissions(path, Permissions::from_mode(0o766)).await?;
+    info!("Listening on socket {NSS_SOCKET}");
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                // e.g. `EMFILE` - lookups must work again as soon as the pressure is gone
+                error!("Error accepting NSS connection: {err}");
+                time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        task::spawn(async move {
+            if let Err(err) = handle_conn(stream).await {
+                debug!("NSS connection error: {err}");
+            }
+        });
+    }
+}
+
+async fn handle_conn(mut stream: UnixStream) -> Result<(), Error> {
+    let peer = Peer::from_stream(&stream)?;
+    let _guard = peer.connect()?;
+
+    let mut buf = [0; HELLO_LEN];
+    read_exact(&mut stream, &mut buf, READ_TIMEOUT).await?;
+    let version = parse_hello(&buf)?;
+    // Always answer with our own version, so the client can log a meaningful error.
+    stream.write_all(&hello()).await?;
+    if version != PROTOCOL_VERSION {
+        return Err(ProtoError::Version(version).into());
+    }
+
+    let mut header = [0; HEADER_LEN];
+    let mut body = Vec::with_capacity(64);
+    loop {
+        match time::timeout(IDLE_TIMEOUT, stream.read_exact(&mut header)).await {
+            Ok(Ok(_)) => {}
+            // the client closed an idle connection
+            Ok(Err(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Err(err)) => return Err(err.into()),
+            Err(_) => return Ok(()),
+        }
+
+        body.resize(parse_request_header(&header)?, 0);
+        read_exact(&mut stream, &mut body, READ_TIMEOUT).await?;
+        let getent = decode_request(&body)?;
+        debug!("NSS request: {getent:?}");
+
+        let resp = match lookup(&peer, getent).await {
+            Ok(bytes) => encode_response(Status::Ok, &bytes)?,
+            // A denied enumeration simply yields no entries, instead of an error logged by
+            // every process doing a `getent passwd`.
+            Err(err) if matches!(err.error, ErrorType::NotFound | ErrorType::Forbidden) => {
+                encode_response(Status::NotFound, &[])?
+            }
+            Err(err)
+                if matches!(
+                    err.error,
+                    ErrorType::Connection | ErrorType::TooManyRequests
+                ) =>
+            {
+                encode_response(Status::TryAgain, err.message.as_bytes())?
+            }
+            Err(err) => encode_response(Status::Unavailable, err.message.as_bytes())?,
+        };
+        stream.write_all(&resp).await?;
+    }
+}
+
+async fn read_exact(
+    stream: &mut UnixStream,
+    buf: &mut [u8],
+    timeout: Duration,
+) -> Result<(), Error> {
+    match time::timeout(timeout, stream.read_exact(buf)).await {
+        Ok(res) => {
+            res?;
+            Ok(())
+        }
+        Err(_) => Err(Error::new(ErrorType::BadRequest, "read timeout")),
+    }
+}
+
+async fn lookup(peer: &Peer, getent: Getent) -> Result<Vec<u8>, Error> {
+    let _guard = peer.acquire()?;
+    peer.check_getent(&getent).await?;
+    getent_bytes(getent).await
+}
@@ -0,0 +1,237 @@
+use crate::api_types::{Getent, GetentResponse};
+use crate::config::Config;
+use crate::error::{Error, ErrorType};
+use crate::groups_local::GroupLocal;
+use crate::handler::getent_bytes;
+use crate::utils::deserialize;
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+use tokio::fs;
+use tokio::net::{UnixListener, UnixStream};
+
+/// Request budget per uid. The amount of entries is bounded by the uids that exist on this
+/// host, since only `root` can connect with an arbitrary one.
+static LIMITS: LazyLock<Mutex<HashMap<u32, Budget>>> = LazyLock::new(Default::default);
+
+struct Budget {
+    /// token bucket, refilled with `rate_limit_per_uid` per second
+    tokens: f64,
+    last_refill: Instant,
+    in_flight: usize,
+    connections: usize,
+}
+
+/// The process on the other end of a socket connection, read via `SO_PEERCRED`.
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl Peer {
+    pub fn from_stream(stream: &UnixStream) -> Result<Self, Error> {
+        let cred = stream.peer_cred()?;
+        Ok(Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        })
+    }
+
+    #[inline]
+    fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// Takes a request slot, which is released when the returned guard is dropped. `root`
+    /// is never limited - it could resolve everything on its own anyway, and system
+    /// services like `sshd` must never be throttled.
+    pub fn acquire(&self) -> Result<RequestGuard, Error> {
+        let config = Config::get();
+        if self.is_root() {
+            return Ok(RequestGuard { uid: None });
+        }
+
+        let mut limits = LIMITS.lock().unwrap();
+        let now = Instant::now();
+        let budget = limits.entry(self.uid).or_insert_with(|| Budget {
+            tokens: config.rate_limit_burst as f64,
+            last_refill: now,
+            in_flight: 0,
+            connections: 0,
+        });
+
+        if config.max_in_flight_per_uid > 0 && budget.in_flight >= config.max_in_flight_per_uid {
+            warn!("Too many requests in flight for uid {}", self.uid);
+            return Err(Error::new(
+                ErrorType::TooManyRequests,
+                "too many requests in flight",
+            ));
+        }
+
+        if config.rate_limit_per_uid > 0 {
+            let elapsed = now.duration_since(budget.last_refill).as_secs_f64();
+            budget.tokens = (budget.tokens + elapsed * config.rate_limit_per_uid as f64)
+                .min(config.rate_limit_burst.max(1) as f64);
+            budget.last_refill = now;
+
+            if budget.tokens < 1.0 {
+                warn!("Rate limit exceeded for uid {}", self.uid);
+                return Err(Error::new(
+                    ErrorType::TooManyRequests,
+                    "rate limit exceeded",
+                ));
+            }
+            budget.tokens -= 1.0;
+        }
+
+        budget.in_flight += 1;
+        Ok(RequestGuard {
+            uid: Some(self.uid),
+        })
+    }
+
+    /// Takes a connection slot for long-lived socket connections, which is
+    /// released when the returned guard is dropped. `root` is never limited.
+    pub fn connect(&self) -> Result<ConnectionGuard, Error> {
+        let config = Config::get();
+        if self.is_root() {
+            return Ok(ConnectionGuard { uid: None });
+        }
+
+        let mut limits = LIMITS.lock().unwrap();
+        let budget = limits.entry(self.uid).or_insert_with(|| Budget {
+            tokens: config.rate_limit_burst as f64,
+            last_refill: Instant::now(),
+            in_flight: 0,
+            connections: 0,
+        });
+
+        if config.max_connections_per_uid > 0
+            && budget.connections >= config.max_connections_per_uid
+        {
+            warn!("Too many open connections for uid {}", self.uid);
+            return Err(Error::new(
+                ErrorType::TooManyRequests,
+                "too many open connections",
+            ));
+        }
+
+        budget.connections += 1;
+        Ok(ConnectionGuard {
+            uid: Some(self.uid),
+        })
+    }
+
+    /// Checks if this peer may do the given lookup. Only full enumerations can be
+    /// restricted, single lookups must always work for everyone.
+    pub async fn check_getent(&self, getent: &Getent) -> Result<(), Error> {
+        if !is_enumeration(getent) || self.may_enumerate().await {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorType::Forbidden,
+            format!("enumeration is restricted: {getent:?}"),
+        ))
+    }
+
+    async fn may_enumerate(&self) -> bool {
+        if !Config::get().restrict_enumeration || self.is_root() {
+            return true;
+        }
+
+        let Some(gid) = enumeration_gid().await else {
+            return false;
+        };
+        if self.gid == gid {
+            return true;
+        }
+
+        // supplementary groups of the connecting process
+        let Some(pid) = self.pid else {
+            return false;
+        };
+        let Ok(status) = fs::read_to_string(format!("/proc/{pid}/status")).await else {
+            return false;
+        };
+        status
+            .lines()
+            .find_map(|l| l.strip_prefix("Groups:"))
+            .is_some_and(|groups| {
+                groups
+                    .split_whitespace()
+                    .any(|g| g.parse::<u32>().ok() == Some(gid))
+            })
+    }
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        Self::from_stream(stream.io()).unwrap_or_else(|err| {
+            warn!("Cannot read peer credentials: {err}");
+            // `nobody`, which ends up with the strictest limits
+            Self {
+                uid: u32::MAX - 1,
+                gid: u32::MAX - 1,
+                pid: None,
+            }
+        })
+    }
+}
+
+/// Releases the in-flight slot on drop.
+pub struct RequestGuard {
+    uid: Option<u32>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if let Some(uid) = self.uid
+            && let Some(budget) = LIMITS.lock().unwrap().get_mut(&uid)
+        {
+            budget.in_flight = budget.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// Releases the connection slot on drop.
+pub struct ConnectionGuard {
+    uid: Option<u32>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(uid) = self.uid
+            && let Some(budget) = LIMITS.lock().unwrap().get_mut(&uid)
+        {
+            budget.connections = budget.connections.saturating_sub(1);
+        }
+    }
+}
+
+#[inline]
+pub fn is_enumeration(getent: &Getent) -> bool {
+    matches!(
+        getent,
+        Getent::Users | Getent::Groups | Getent::Hosts | Getent::Netgroups
+    )
+}
+
+/// Resolves `enumeration_group` from `/etc/group` first and falls back to Rauthy.
+pub async fn enumeration_gid() -> Option<u32> {
+    let name = Config::get().enumeration_group.as_ref()?;
+
+    if let Ok(Some(local)) = GroupLocal::read_id(name).await {
+        return Some(local.id);
+    }
+    let bytes = getent_bytes(Getent::Groupname(name.clone())).await.ok()?;
+    match deserialize::<GetentResponse>(&bytes).ok()? {
+        GetentResponse::Group(group) => Some(group.id),
+        _ => None,
+    }
+}
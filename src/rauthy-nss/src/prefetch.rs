@@ -0,0 +1,69 @@
+use crate::RAUTHY_HEALTHY;
+use crate::api_types::{Getent, GetentResponse};
+use crate::config::Config;
+use crate::handler::refresh;
+use crate::utils::deserialize;
+use log::{debug, info};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::{task, time};
+
+/// Keeps the full listings in the cache fresh. Each listing is indexed by name and id when
+/// it is received, so on small deployments, almost no lookup ever reaches the network.
+pub fn spawn() {
+    let config = Config::get();
+    if config.prefetch_interval == 0 {
+        debug!("Prefetching is disabled");
+        return;
+    }
+
+    task::spawn(async {
+        let config = Config::get();
+        let mut interval = time::interval(Duration::from_secs(config.prefetch_interval));
+        let mut listings = vec![
+            Getent::Users,
+            Getent::Groups,
+            Getent::Hosts,
+            Getent::Netgroups,
+        ];
+
+        loop {
+            interval.tick().await;
+            if !RAUTHY_HEALTHY.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let mut too_large = Vec::new();
+            for (i, getent) in listings.iter().enumerate() {
+                let len = match refresh(getent.clone()).await {
+                    Ok(bytes) => match deserialize::<GetentResponse>(&bytes) {
+                        Ok(GetentResponse::Users(v)) => v.len(),
+                        Ok(GetentResponse::Groups(v)) => v.len(),
+                        Ok(GetentResponse::Hosts(v)) => v.len(),
+                        Ok(GetentResponse::Netgroups(v)) => v.len(),
+                        _ => 0,
+                    },
+                    Err(err) => {
+                        debug!("Error prefetching {getent:?}: {err}");
+                        continue;
+                    }
+                };
+
+                if len > config.prefetch_max_entries {
+                    info!(
+                        "{getent:?} has {len} entries, which is more than prefetch_max_entries - \
+                        disabling prefetch for it"
+                    );
+                    too_large.push(i);
+                }
+            }
+
+            for i in too_large.into_iter().rev() {
+                listings.remove(i);
+            }
+            if listings.is_empty() {
+                return;
+            }
+        }
+    });
+}
@@ -1,9 +1,20 @@
-use crate::PROXY_SOCKET;
+use crate::config::Config;
 use crate::handler::get_root;
 use crate::handler::groups::*;
 use crate::handler::hosts::*;
+use crate::handler::netgroups::*;
+use crate::handler::sessions::*;
+use crate::handler::stats::*;
+use crate::handler::sudoers::*;
 use crate::handler::users::*;
-use axum::{Router, routing::get};
+use crate::handler::whoami::*;
+use crate::peer::Peer;
+use crate::{ID_FLOOR_PATH, PROXY_SOCKET};
+use axum::Router;
+use axum::extract::{ConnectInfo, Request};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::{get, post};
 use log::{debug, info};
 use std::fs::Permissions;
 use std::os::unix::fs::PermissionsExt;
@@ -26,10 +37,15 @@ pub async fn run() -> anyhow::Result<()> {
     // everywhere.
     fs::set_permissions(parent, Permissions::from_mode(0o755)).await?;
 
+    fs::write(ID_FLOOR_PATH, Config::get().id_floor.to_string()).await?;
+    fs::set_permissions(ID_FLOOR_PATH, Permissions::from_mode(0o644)).await?;
+
     let uds = UnixListener::bind(path)?;
     // The socket must be available for world.
     // It does not leak any information a normal user on the system would not be able to see anyway.
     // It only exports NSS information, that e.g. anyone could read by from /etc/passwd by default.
+    // Each connection is identified via `SO_PEERCRED` though, which is used for rate limiting
+    // and the optional enumeration restriction.
     //
     // TODO find a way to explicitly set the sticky bit from safe rust. Is this even possible?
     fs::set_permissions(PROXY_SOCKET, Permissions::from_mode(0o766)).await?;
@@ -41,6 +57,11 @@ pub async fn run() -> anyhow::Result<()> {
 
     let app = Router::new()
         .route("/", get(get_root))
+        .route("/sessions/close", post(post_session_close))
+        .route("/sessions/open", post(post_session_open))
+        .route("/stats", get(get_stats))
+        .route("/sudoers", get(get_sudoers))
+        .route("/whoami", get(get_whoami))
         .nest(
             "/getent",
             Router::new()
@@ -50,11 +71,14 @@ pub async fn run() -> anyhow::Result<()> {
                 .route("/hosts", get(get_hosts))
                 .route("/hosts/ip/{ip}", get(get_host_by_ip))
                 .route("/hosts/name/{name}", get(get_host_by_name))
+                .route("/netgroups", get(get_netgroups))
+                .route("/netgroups/name/{name}", get(get_netgroup_by_name))
                 .route("/users", get(get_users))
                 .route("/users/uid/{uid}", get(get_user_by_uid))
                 .route("/users/name/{name}", get(get_user_by_name)),
         )
-        .into_make_service();
+        .layer(middleware::from_fn(limit_peer))
+        .into_make_service_with_connect_info::<Peer>();
 
     info!("Listening on socket {PROXY_SOCKET}");
     axum::serve(uds, app).await?;
@@ -62,6 +86,15 @@ pub async fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn limit_peer(
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    req: Request,
+    next: Next,
+) -> Result<Response, crate::error::Error> {
+    let _guard = peer.acquire()?;
+    Ok(next.run(req).await)
+}
+
 async fn restore_selinux_labels() {
     if fs::try_exists("/usr/sbin/restorecon").await.ok() != Some(true) {
         debug!("/usr/sbin/restorecon not found - skipping SELinux label restore");
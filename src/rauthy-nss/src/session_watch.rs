@@ -0,0 +1,338 @@
+use crate::api_types::{Getent, GetentResponse};
+use crate::config::Config;
+use crate::error::{Error, ErrorType};
+use crate::http_client::HttpClient;
+use crate::logind;
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use reqwest::StatusCode;
+use reqwest::header::AUTHORIZATION;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::{fs, task, time};
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `ACTIVE` is mirrored into this file, so sessions opened before a restart of `rauthy-nss`
+/// are still validated afterward. It contains `PamToken` ids and is only readable by `root`.
+#[cfg(debug_assertions)]
+static STATE_PATH: &str = "/tmp/rauthy/session_watch.toml";
+#[cfg(not(debug_assertions))]
+static STATE_PATH: &str = "/run/rauthy/session_watch.toml";
+
+/// Users with active Rauthy sessions on this host by uid, registered by the PAM module.
+static ACTIVE: LazyLock<Mutex<HashMap<u32, ActiveUser>>> = LazyLock::new(Default::default);
+
+/// Sent by the PAM module from `open_session`, as TOML like all other proxy payloads.
+#[derive(Debug, Deserialize)]
+pub struct SessionOpenRequest {
+    pub user_id: String,
+    pub username: String,
+    pub uid: u32,
+    pub token_id: String,
+    pub token_exp: i64,
+}
+
+impl SessionOpenRequest {
+    pub fn parse(body: &str) -> Result<Self, Error> {
+        toml::from_str(body).map_err(|err| Error::new(ErrorType::BadRequest, err.message()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ActiveUser {
+    user_id: String,
+    username: String,
+    token_id: String,
+    token_exp: i64,
+    /// PIDs of the processes holding the PAM handles, which open and close the session
+    pids: HashSet<i32>,
+    #[serde(skip)]
+    terminating: bool,
+}
+
+/// Registers a session. The newest token always replaces an older one, because it has the
+/// longest lifetime left.
+pub fn open(req: SessionOpenRequest, pid: i32) {
+    let mut active = ACTIVE.lock().unwrap();
+    let user = active.entry(req.uid).or_insert_with(|| ActiveUser {
+        user_id: String::default(),
+        username: String::default(),
+        token_id: String::default(),
+        token_exp: 0,
+        pids: HashSet::new(),
+        terminating: false,
+    });
+    if req.token_exp >= user.token_exp {
+        user.user_id = req.user_id;
+        user.username = req.username;
+        user.token_id = req.token_id;
+        user.token_exp = req.token_exp;
+    }
+    user.pids.insert(pid);
+    debug!("Session opened for uid {} by pid {pid}", req.uid);
+    save_state(&active);
+}
+
+/// Removes the session opened by `pid`, if there was any.
+pub fn close(pid: i32) {
+    let mut active = ACTIVE.lock().unwrap();
+    active.retain(|uid, user| {
+        if user.pids.remove(&pid) {
+            debug!("Session closed for uid {uid} by pid {pid}");
+        }
+        !user.pids.is_empty()
+    });
+    save_state(&active);
+}
+
+/// Restores the sessions registered before a restart. Sessions which have been closed in
+/// the meantime are dropped with the next `prune()`.
+fn restore() {
+    let content = match std::fs::read_to_string(STATE_PATH) {
+        Ok(c) => c,
+        Err(err) if err.kind() == ErrorKind::NotFound => return,
+        Err(err) => {
+            error!("Cannot read {STATE_PATH}: {err}");
+            return;
+        }
+    };
+    match toml::from_str::<BTreeMap<String, ActiveUser>>(&content) {
+        Ok(state) => {
+            let mut active = ACTIVE.lock().unwrap();
+            for (uid, user) in state {
+                if let Ok(uid) = uid.parse::<u32>() {
+                    active.insert(uid, user);
+                }
+            }
+            info!("Restored sessions of {} users", active.len());
+        }
+        Err(err) => error!("Cannot parse {STATE_PATH}: {err}"),
+    }
+}
+
+fn save_state(active: &HashMap<u32, ActiveUser>) {
+    let state = active
+        .iter()
+        .map(|(uid, user)| (uid.to_string(), user))
+        .collect::<BTreeMap<_, _>>();
+
+    let res = (|| {
+        let content = toml::to_string(&state).map_err(io::Error::other)?;
+        let path_tmp = format!("{STATE_PATH}.tmp");
+        let _ = std::fs::remove_file(&path_tmp);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path_tmp)?;
+        file.write_all(content.as_bytes())?;
+        std::fs::rename(&path_tmp, STATE_PATH)
+    })();
+    if let Err(err) = res {
+        error!("Cannot save sessions to {STATE_PATH}: {err}");
+    }
+}
+
+pub fn spawn() {
+    restore();
+
+    let config = Config::get();
+    if !config.session_validate_enable {
+        debug!("Session validation is disabled");
+        return;
+    }
+
+    task::spawn(async {
+        let secs = Config::get().session_validate_interval.max(10);
+        let mut interval = time::interval(Duration::from_secs(secs));
+        loop {
+            interval.tick().await;
+            prune().await;
+            validate_all().await;
+        }
+    });
+}
+
+/// Drops sessions, whose `close_session` never arrived, e.g. after a crash.
+async fn prune() {
+    let pids = ACTIVE
+        .lock()
+        .unwrap()
+        .values()
+        .flat_map(|u| u.pids.iter().copied())
+        .collect::<Vec<_>>();
+
+    let mut dead = Vec::new();
+    for pid in pids {
+        if fs::try_exists(format!("/proc/{pid}")).await.ok() != Some(true) {
+            dead.push(pid);
+        }
+    }
+    for pid in dead {
+        close(pid);
+    }
+}
+
+async fn validate_all() {
+    let now = Utc::now().timestamp();
+    let users = ACTIVE
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, u)| !u.terminating)
+        .map(|(uid, u)| {
+            (
+                *uid,
+                u.user_id.clone(),
+                u.username.clone(),
+                u.token_id.clone(),
+                u.token_exp,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (uid, user_id, username, token_id, token_exp) in users {
+        // Rauthy rejects expired tokens, which does not mean the user has been disabled.
+        // In that case, we can only ask Rauthy about the user with the host credentials.
+        let res = if token_exp < now {
+            debug!("PamToken for {username} has expired - looking up the user instead");
+            user_exists(uid, &username).await
+        } else {
+            validate(&user_id, &token_id).await
+        };
+
+        match res {
+            Ok(true) => debug!("Sessions of {username} are still valid"),
+            Ok(false) => {
+                warn!("{username} has been revoked in Rauthy - terminating its sessions");
+                terminate(uid);
+            }
+            // never kill sessions, just because Rauthy cannot be reached
+            Err(err) => error!("Cannot validate sessions of {username}: {err}"),
+        }
+    }
+}
+
+/// Returns `true` if `uid` has at least one active Rauthy session on this host.
+pub fn is_active(uid: u32) -> bool {
+    ACTIVE.lock().unwrap().contains_key(&uid)
+}
+
+/// `Ok(false)` only if Rauthy explicitly rejected the token.
+async fn validate(user_id: &str, token_id: &str) -> Result<bool, Error> {
+    let url = format!("{}auth/v1/pam/validate/{user_id}", Config::get().rauthy_url);
+    let res = HttpClient::client()
+        .get(url)
+        .header(AUTHORIZATION, format!("PamToken {token_id}"))
+        .send()
+        .await?;
+
+    match res.status() {
+        s if s.is_success() => Ok(true),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(false),
+        s => Err(Error::new(
+            ErrorType::Connection,
+            format!("Rauthy returned {s} for the PamToken validation"),
+        )),
+    }
+}
+
+/// `Ok(false)` only if Rauthy explicitly answered, that this host does not know the user
+/// anymore, or that the uid belongs to someone else now.
+async fn user_exists(uid: u32, username: &str) -> Result<bool, Error> {
+    match HttpClient::getent(&Getent::UserId(uid)).await? {
+        Some(GetentResponse::User(user)) => Ok(user.name == username),
+        None => Ok(false),
+        Some(resp) => Err(Error::new(
+            ErrorType::Internal,
+            format!("Unexpected response from Rauthy for uid {uid}: {resp:?}"),
+        )),
+    }
+}
+
+/// Shows `session_terminate_message` on all terminals of `uid` and terminates all of its
+/// sessions via `systemd-logind` after `session_terminate_grace` seconds.
+pub fn terminate(uid: u32) {
+    if let Some(user) = ACTIVE.lock().unwrap().get_mut(&uid) {
+        if user.terminating {
+            return;
+        }
+        user.terminating = true;
+    }
+
+    task::spawn(async move {
+        let config = Config::get();
+        let grace = config.session_terminate_grace;
+        let msg = config
+            .session_terminate_message
+            .replace("{grace}", &grace.to_string());
+        // A user can stop the output of a terminal at any time, which must never be able
+        // to prevent the termination.
+        match time::timeout(NOTIFY_TIMEOUT, notify_terminals(uid, &msg)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => error!("Cannot notify the terminals of uid {uid}: {err}"),
+            Err(_) => error!("Timeout notifying the terminals of uid {uid}"),
+        }
+
+        time::sleep(Duration::from_secs(grace)).await;
+        match logind::terminate_user(uid).await {
+            Ok(()) => {
+                let mut active = ACTIVE.lock().unwrap();
+                active.remove(&uid);
+                save_state(&active);
+            }
+            Err(err) => {
+                error!("{err}");
+                // try again with the next validation
+                if let Some(user) = ACTIVE.lock().unwrap().get_mut(&uid) {
+                    user.terminating = false;
+                }
+            }
+        }
+    });
+}
+
+/// Writes `msg` to all terminals owned by `uid`, just like `write(1)` does.
+async fn notify_terminals(uid: u32, msg: &str) -> Result<(), Error> {
+    let mut ttys = Vec::new();
+    for (dir, prefix) in [("/dev/pts", ""), ("/dev", "tty")] {
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let is_tty = name
+                .to_str()
+                .and_then(|n| n.strip_prefix(prefix))
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+            if is_tty && entry.metadata().await.is_ok_and(|m| m.uid() == uid) {
+                ttys.push(entry.path());
+            }
+        }
+    }
+
+    let text = format!("\r\n\x07{}\r\n", msg.replace('\n', "\r\n"));
+    for path in ttys {
+        // never block on a stopped terminal and never make it our controlling one
+        let res = fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+            .open(&path)
+            .await;
+        match res {
+            Ok(mut tty) => {
+                if let Err(err) = tty.write_all(text.as_bytes()).await {
+                    warn!("Cannot write to {}: {err}", path.display());
+                }
+            }
+            Err(err) => warn!("Cannot open {}: {err}", path.display()),
+        }
+    }
+
+    info!("Notified uid {uid} about the session termination");
+    Ok(())
+}
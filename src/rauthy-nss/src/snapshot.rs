@@ -0,0 +1,131 @@
+use crate::api_types::{Getent, GetentResponse};
+use crate::config::Config;
+use crate::error::{Error, ErrorType};
+use crate::handler::getent_bytes;
+use crate::peer::enumeration_gid;
+use crate::utils::deserialize;
+use crate::{RAUTHY_HEALTHY, SNAPSHOT_PATH};
+use chrono::Utc;
+use log::{debug, error, info};
+use rauthy_nss_proto::{SNAPSHOT_STALE_OFFSET, encode_snapshot};
+use std::fs::Permissions;
+use std::os::unix::fs::{FileExt, PermissionsExt};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::{fs, task, time};
+
+static REBUILD: Notify = Notify::const_new();
+
+pub fn spawn() {
+    let config = Config::get();
+    if !config.snapshot_enable {
+        debug!("NSS snapshot is disabled");
+        // an old one would be used until it expires otherwise
+        let _ = std::fs::remove_file(SNAPSHOT_PATH);
+        return;
+    }
+
+    task::spawn(async {
+        let secs = Config::get().snapshot_interval.max(1);
+        let mut interval = time::interval(Duration::from_secs(secs));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = REBUILD.notified() => {}
+            }
+
+            // An outdated snapshot will simply expire, and the NSS module falls back to the
+            // socket, which handles an unhealthy Rauthy properly.
+            if !RAUTHY_HEALTHY.load(Ordering::Relaxed) {
+                debug!("Rauthy is unhealthy - skipping NSS snapshot");
+                continue;
+            }
+            if let Err(err) = publish(secs).await {
+                error!("Error publishing NSS snapshot: {err}");
+            }
+        }
+    });
+}
+
+/// Rebuilds the snapshot right away instead of waiting for the next interval.
+pub fn rebuild() {
+    REBUILD.notify_one();
+}
+
+async fn publish(interval: u64) -> Result<(), Error> {
+    // A listing that does not exist, e.g. no hosts at all, is simply empty.
+    let users = match lookup(Getent::Users).await? {
+        None => Vec::new(),
+        Some(GetentResponse::Users(users)) => users,
+        Some(_) => return Err(unexpected()),
+    };
+    let groups = match lookup(Getent::Groups).await? {
+        None => Vec::new(),
+        Some(GetentResponse::Groups(groups)) => groups,
+        Some(_) => return Err(unexpected()),
+    };
+    let hosts = match lookup(Getent::Hosts).await? {
+        None => Vec::new(),
+        Some(GetentResponse::Hosts(hosts)) => hosts,
+        Some(_) => return Err(unexpected()),
+    };
+
+    // 2 intervals make sure there is never a gap between 2 snapshots
+    let expires = Utc::now().timestamp() + 2 * interval as i64;
+    let bytes = encode_snapshot(&users, &groups, &hosts, expires)?;
+
+    let path = Path::new(SNAPSHOT_PATH);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, &bytes).await?;
+    // The snapshot contains full listings, so it must follow the enumeration restriction.
+    // Everyone else falls back to single lookups via the socket.
+    if Config::get().restrict_enumeration {
+        match enumeration_gid().await {
+            Some(gid) => {
+                std::os::unix::fs::chown(&tmp, Some(0), Some(gid))?;
+                fs::set_permissions(&tmp, Permissions::from_mode(0o640)).await?;
+            }
+            None => fs::set_permissions(&tmp, Permissions::from_mode(0o600)).await?,
+        }
+    } else {
+        fs::set_permissions(&tmp, Permissions::from_mode(0o644)).await?;
+    }
+
+    // Processes keep their mapping of the old file after the rename. The stale flag tells
+    // them to map the new one, without having to `stat()` the path on each lookup.
+    let old = std::fs::OpenOptions::new().write(true).open(path).ok();
+    fs::rename(&tmp, path).await?;
+    if let Some(old) = old {
+        old.write_all_at(&1u32.to_le_bytes(), SNAPSHOT_STALE_OFFSET as u64)?;
+    }
+
+    info!(
+        "Published NSS snapshot with {} users, {} groups and {} hosts ({} bytes)",
+        users.len(),
+        groups.len(),
+        hosts.len(),
+        bytes.len()
+    );
+    Ok(())
+}
+
+/// Goes through the cache and applies the same local id collision checks as NSS lookups.
+async fn lookup(getent: Getent) -> Result<Option<GetentResponse>, Error> {
+    match getent_bytes(getent).await {
+        Ok(bytes) => Ok(Some(deserialize::<GetentResponse>(&bytes)?)),
+        Err(err) if err.error == ErrorType::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn unexpected() -> Error {
+    Error::new(
+        ErrorType::Internal,
+        "Unexpected response for a full listing for the NSS snapshot",
+    )
+}
@@ -0,0 +1,227 @@
+use crate::RAUTHY_HEALTHY;
+use crate::api_types::SudoRule;
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::error::{Error, ErrorType};
+use crate::whoami;
+use log::{debug, error, info, warn};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::{fs, task, time};
+
+#[cfg(debug_assertions)]
+static SUDOERS_PATH: &str = "/tmp/rauthy/sudoers.d/rauthy";
+#[cfg(not(debug_assertions))]
+static SUDOERS_PATH: &str = "/etc/sudoers.d/rauthy";
+// `sudo` ignores files containing a `.` in `#includedir`, so a half-written file can never
+// be picked up.
+#[cfg(debug_assertions)]
+static SUDOERS_PATH_TMP: &str = "/tmp/rauthy/sudoers.d/.rauthy.tmp";
+#[cfg(not(debug_assertions))]
+static SUDOERS_PATH_TMP: &str = "/etc/sudoers.d/.rauthy.tmp";
+
+static CACHE_KEY: &str = "$sudoers$";
+
+/// All `sudoers` command tags, which must never show up as a command.
+static TAGS: &[&str] = &[
+    "EXEC",
+    "NOEXEC",
+    "FOLLOW",
+    "NOFOLLOW",
+    "INTERCEPT",
+    "NOINTERCEPT",
+    "LOG_INPUT",
+    "NOLOG_INPUT",
+    "LOG_OUTPUT",
+    "NOLOG_OUTPUT",
+    "MAIL",
+    "NOMAIL",
+    "PASSWD",
+    "NOPASSWD",
+    "SETENV",
+    "NOSETENV",
+];
+
+static HEADER: &str = r#"# Managed by rauthy-nss - DO NOT EDIT
+# This file is generated from the sudo rules for this host in Rauthy
+# and will be overwritten with the next sync.
+"#;
+
+/// Returns the rendered sudoers file for this host, either from cache or freshly fetched
+/// via `whoami`.
+pub async fn rendered() -> Result<String, Error> {
+    if let Some(Some(bytes)) = Cache::get(CACHE_KEY.to_string()).await {
+        debug!("Cache hit");
+        return String::from_utf8(bytes)
+            .map_err(|err| Error::new(ErrorType::Internal, err.to_string()));
+    }
+
+    if !RAUTHY_HEALTHY.load(Ordering::Relaxed) {
+        return Err(Error::new(
+            ErrorType::Connection,
+            "Rauthy unhealthy: sudo rules",
+        ));
+    }
+
+    let details = whoami::fetch().await?;
+    let content = render(&details.sudo_rules)?;
+    Cache::set(
+        CACHE_KEY.to_string(),
+        Some(content.as_bytes().to_vec()),
+        Config::get().cache_ttl_groups,
+    )
+    .await;
+
+    Ok(content)
+}
+
+pub fn spawn_generator() {
+    let config = Config::get();
+    if !config.sudoers_enable {
+        debug!("sudoers generation is disabled");
+        return;
+    }
+
+    task::spawn(async {
+        let mut interval = time::interval(Duration::from_secs(Config::get().sudoers_interval));
+        loop {
+            interval.tick().await;
+
+            match rendered().await {
+                Ok(content) => {
+                    if let Err(err) = write_if_changed(&content).await {
+                        error!("Error updating {SUDOERS_PATH}: {err}");
+                    }
+                }
+                Err(err) => {
+                    // keep the existing file - we must not remove privileges just
+                    // because Rauthy is temporarily unreachable
+                    warn!("Cannot fetch sudo rules, keeping {SUDOERS_PATH}: {err}");
+                }
+            }
+        }
+    });
+}
+
+fn render(rules: &[SudoRule]) -> Result<String, Error> {
+    let mut res = String::from(HEADER);
+
+    for rule in rules {
+        if let Err(err) = validate(rule) {
+            warn!("Skipping invalid sudo rule {rule:?}: {err}");
+            continue;
+        }
+
+        write!(
+            res,
+            "\n%{} ALL=({}) {}{}",
+            rule.group,
+            rule.runas.as_deref().unwrap_or("ALL"),
+            if rule.nopasswd { "NOPASSWD: " } else { "" },
+            if rule.commands.is_empty() {
+                "ALL".to_string()
+            } else {
+                rule.commands.join(", ")
+            }
+        )?;
+    }
+    res.push('\n');
+
+    Ok(res)
+}
+
+/// Basic sanity checks. The final file is validated with `visudo` anyway, but we never want
+/// Rauthy data to be able to inject additional lines or comment out the rest of a rule.
+/// Anything that could change the meaning of the rule, like a list separator, a `Runas_Spec`
+/// or a tag such as `NOPASSWD:`, is rejected for commands and runas values alike. Commands
+/// must be an absolute path, optionally negated, or `ALL`.
+fn validate(rule: &SudoRule) -> Result<(), &'static str> {
+    let is_name = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+    };
+    let is_safe = |s: &str| {
+        !s.trim().is_empty()
+            && !s.contains(|c: char| c.is_control() || "#,:\\=()".contains(c))
+            && !s
+                .split_whitespace()
+                .next()
+                .is_some_and(|first| TAGS.contains(&first))
+    };
+    let is_command =
+        |s: &str| s == "ALL" || (s.strip_prefix('!').unwrap_or(s).starts_with('/') && is_safe(s));
+
+    if !is_name(&rule.group) {
+        return Err("invalid group name");
+    }
+    if let Some(runas) = &rule.runas
+        && !runas.split(':').all(|s| is_name(s) && is_safe(s))
+    {
+        return Err("invalid runas");
+    }
+    if !rule.commands.iter().all(|cmd| is_command(cmd)) {
+        return Err("invalid command");
+    }
+
+    Ok(())
+}
+
+async fn write_if_changed(content: &str) -> Result<(), Error> {
+    if fs::read_to_string(SUDOERS_PATH).await.ok().as_deref() == Some(content) {
+        debug!("{SUDOERS_PATH} is up to date");
+        return Ok(());
+    }
+
+    if let Some(parent) = Path::new(SUDOERS_PATH).parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let _ = fs::remove_file(SUDOERS_PATH_TMP).await;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o440)
+        .open(SUDOERS_PATH_TMP)
+        .await?;
+    file.write_all(content.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    let out = Command::new("/usr/sbin/visudo")
+        .arg("-c")
+        .arg("-q")
+        .arg("-f")
+        .arg(SUDOERS_PATH_TMP)
+        .output()
+        .await;
+    match out {
+        Ok(out) if out.status.success() => {}
+        Ok(out) => {
+            let _ = fs::remove_file(SUDOERS_PATH_TMP).await;
+            return Err(Error::new(
+                ErrorType::BadRequest,
+                format!(
+                    "visudo validation failed, keeping the old file: {}",
+                    String::from_utf8_lossy(&out.stderr)
+                ),
+            ));
+        }
+        Err(err) => {
+            let _ = fs::remove_file(SUDOERS_PATH_TMP).await;
+            return Err(Error::new(
+                ErrorType::Internal,
+                format!("Cannot execute /usr/sbin/visudo: {err}"),
+            ));
+        }
+    }
+
+    fs::rename(SUDOERS_PATH_TMP, SUDOERS_PATH).await?;
+    info!("Updated {SUDOERS_PATH}");
+
+    Ok(())
+}
@@ -0,0 +1,433 @@
+use crate::VERSION;
+use crate::api_types::{Getent, GetentResponse, GroupResponse, UserResponse};
+use crate::config::Config;
+use crate::error::{Error, ErrorType};
+use crate::handler::getent_bytes;
+use crate::peer::Peer;
+use crate::utils::deserialize;
+use log::{debug, error, info};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{fs, task, time};
+
+// `nss-systemd` and `userdbctl` query every socket in this dir. The file name is the
+// service name clients must send with each request.
+#[cfg(debug_assertions)]
+static USERDB_SOCKET: &str = "/tmp/rauthy/userdb/io.rauthy";
+#[cfg(not(debug_assertions))]
+static USERDB_SOCKET: &str = "/run/systemd/userdb/io.rauthy";
+static SERVICE: &str = "io.rauthy";
+
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+/// Idle connections are closed after this. Clients usually send a single call anyway.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// A lookup call is tiny. Anything bigger is dropped, before we buffer it.
+const MAX_MESSAGE_LEN: u64 = 64 * 1024;
+
+static ERR_NO_RECORD: &str = "io.systemd.UserDatabase.NoRecordFound";
+static ERR_BAD_SERVICE: &str = "io.systemd.UserDatabase.BadService";
+static ERR_CONFLICTING_RECORD: &str = "io.systemd.UserDatabase.ConflictingRecordFound";
+static ERR_SERVICE_NOT_AVAILABLE: &str = "io.systemd.UserDatabase.ServiceNotAvailable";
+static ERR_ENUMERATION: &str = "io.systemd.UserDatabase.EnumerationNotSupported";
+static ERR_EXPECTED_MORE: &str = "org.varlink.service.ExpectedMore";
+static ERR_METHOD_NOT_FOUND: &str = "org.varlink.service.MethodNotFound";
+static ERR_INVALID_PARAMETER: &str = "org.varlink.service.InvalidParameter";
+
+/// A single varlink call. Messages are JSON objects terminated by a `NUL` byte.
+#[derive(Debug, Deserialize)]
+struct Call {
+    method: String,
+    #[serde(default)]
+    parameters: Value,
+    /// the client accepts multiple replies
+    #[serde(default)]
+    more: bool,
+    /// the client does not want any reply at all
+    #[serde(default)]
+    oneway: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LookupParams {
+    uid: Option<u32>,
+    user_name: Option<String>,
+    gid: Option<u32>,
+    group_name: Option<String>,
+    #[serde(default)]
+    service: String,
+}
+
+/// The result of a call before it is written to the client.
+enum Reply {
+    /// Sent with `continues: true` for all but the last one.
+    Values(Vec<Value>),
+    Error(&'static str, Value),
+}
+
+pub fn spawn() {
+    if !Config::get().userdb_enable {
+        debug!("systemd userdb provider is disabled");
+        return;
+    }
+
+    task::spawn(async {
+        if let Err(err) = run().await {
+            error!("systemd userdb provider error: {err}");
+        }
+    });
+}
+
+async fn run() -> Result<(), Error> {
+    let path = Path::new(USERDB_SOCKET);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let _ = fs::remove_file(path).await;
+
+    let listener = UnixListener::bind(path)?;
+    // same as `/etc/passwd` - every process must be able to resolve users
+    fs::set_permissions(path, Permissions::from_mode(0o666)).await?;
+    info!("systemd userdb provider listening on {USERDB_SOCKET}");
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                // e.g. `EMFILE` - lookups must work again as soon as the pressure is gone
+                error!("Error accepting userdb connection: {err}");
+                time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        task::spawn(async move {
+            if let Err(err) = handle_conn(stream).await {
+                debug!("userdb connection error: {err}");
+            }
+        });
+    }
+}
+
+async fn handle_conn(stream: UnixStream) -> Result<(), Error> {
+    let peer = Peer::from_stream(&stream)?;
+    let _guard = peer.connect()?;
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let mut buf = Vec::with_capacity(256);
+
+    loop {
+        buf.clear();
+        let mut msg = (&mut read).take(MAX_MESSAGE_LEN);
+        let len = match time::timeout(IDLE_TIMEOUT, msg.read_until(0, &mut buf)).await {
+            Ok(res) => res?,
+            Err(_) => return Ok(()),
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        if buf.last() == Some(&0) {
+            buf.pop();
+        } else if len as u64 == MAX_MESSAGE_LEN {
+            return Err(Error::new(
+                ErrorType::BadRequest,
+                format!("message exceeds {MAX_MESSAGE_LEN} bytes"),
+            ));
+        }
+
+        let call = serde_json::from_slice::<Call>(&buf)?;
+        debug!("userdb call: {} {}", call.method, call.parameters);
+        let reply = handle_call(&peer, &call).await;
+
+        if call.oneway {
+            continue;
+        }
+        for msg in encode(reply) {
+            write.write_all(&msg).await?;
+        }
+    }
+}
+
+async fn handle_call(peer: &Peer, call: &Call) -> Reply {
+    if call.method == "org.varlink.service.GetInfo" {
+        return Reply::Values(vec![json!({
+            "vendor": "Rauthy",
+            "product": "rauthy-nss",
+            "version": VERSION,
+            "url": "https://github.com/sebadob/rauthy-pam-nss",
+            "interfaces": ["io.systemd.UserDatabase", "org.varlink.service"],
+        })]);
+    }
+
+    let params = if call.parameters.is_null() {
+        LookupParams::default()
+    } else {
+        match serde_json::from_value::<LookupParams>(call.parameters.clone()) {
+            Ok(p) => p,
+            Err(err) => {
+                return Reply::Error(
+                    ERR_INVALID_PARAMETER,
+                    json!({ "parameter": err.to_string() }),
+                );
+            }
+        }
+    };
+
+    let _guard = match peer.acquire() {
+        Ok(guard) => guard,
+        Err(err) => {
+            debug!("userdb lookup denied: {err}");
+            return Reply::Error(ERR_NO_RECORD, json!({}));
+        }
+    };
+
+    let res = match call.method.as_str() {
+        "io.systemd.UserDatabase.GetUserRecord" => get_user_record(peer, &params, call.more).await,
+        "io.systemd.UserDatabase.GetGroupRecord" => {
+            get_group_record(peer, &params, call.more).await
+        }
+        "io.systemd.UserDatabase.GetMemberships" => get_memberships(peer, &params, call.more).await,
+        _ => {
+            return Reply::Error(ERR_METHOD_NOT_FOUND, json!({ "method": call.method }));
+        }
+    };
+
+    match res {
+        Ok(reply) => reply,
+        // Rauthy is unreachable or unhealthy -> systemd continues with the next source
+        Err(err) if err.error == ErrorType::Connection => {
+            debug!("userdb lookup error: {err}");
+            Reply::Error(ERR_SERVICE_NOT_AVAILABLE, json!({}))
+        }
+        Err(err) => {
+            debug!("userdb lookup error: {err}");
+            Reply::Error(ERR_NO_RECORD, json!({}))
+        }
+    }
+}
+
+async fn get_user_record(peer: &Peer, params: &LookupParams, more: bool) -> Result<Reply, Error> {
+    if params.service != SERVICE {
+        return Ok(Reply::Error(ERR_BAD_SERVICE, json!({})));
+    }
+
+    let user = match (&params.user_name, params.uid) {
+        (Some(name), uid) => {
+            let Some(user) = user(Getent::Username(name.clone())).await? else {
+                return Ok(Reply::Error(ERR_NO_RECORD, json!({})));
+            };
+            if uid.is_some_and(|uid| uid != user.id) {
+                return Ok(Reply::Error(ERR_CONFLICTING_RECORD, json!({})));
+            }
+            user
+        }
+        (None, Some(uid)) => {
+            let Some(user) = user(Getent::UserId(uid)).await? else {
+                return Ok(Reply::Error(ERR_NO_RECORD, json!({})));
+            };
+            user
+        }
+        (None, None) => {
+            if !more {
+                return Ok(Reply::Error(ERR_EXPECTED_MORE, json!({})));
+            }
+            if peer.check_getent(&Getent::Users).await.is_err() {
+                return Ok(Reply::Error(ERR_ENUMERATION, json!({})));
+            }
+
+            let groups = groups().await?;
+            let records = users()
+                .await?
+                .iter()
+                .map(|u| json!({ "record": user_record(u, &groups), "incomplete": false }))
+                .collect();
+            return Ok(Reply::Values(records));
+        }
+    };
+
+    let groups = groups().await?;
+    Ok(Reply::Values(vec![json!({
+        "record": user_record(&user, &groups),
+        "incomplete": false,
+    })]))
+}
+
+async fn get_group_record(peer: &Peer, params: &LookupParams, more: bool) -> Result<Reply, Error> {
+    if params.service != SERVICE {
+        return Ok(Reply::Error(ERR_BAD_SERVICE, json!({})));
+    }
+
+    let group = match (&params.group_name, params.gid) {
+        (Some(name), gid) => {
+            let Some(group) = group(Getent::Groupname(name.clone())).await? else {
+                return Ok(Reply::Error(ERR_NO_RECORD, json!({})));
+            };
+            if gid.is_some_and(|gid| gid != group.id) {
+                return Ok(Reply::Error(ERR_CONFLICTING_RECORD, json!({})));
+            }
+            group
+        }
+        (None, Some(gid)) => {
+            let Some(group) = group(Getent::GroupId(gid)).await? else {
+                return Ok(Reply::Error(ERR_NO_RECORD, json!({})));
+            };
+            group
+        }
+        (None, None) => {
+            if !more {
+                return Ok(Reply::Error(ERR_EXPECTED_MORE, json!({})));
+            }
+            if peer.check_getent(&Getent::Groups).await.is_err() {
+                return Ok(Reply::Error(ERR_ENUMERATION, json!({})));
+            }
+
+            let records = groups()
+                .await?
+                .iter()
+                .map(|g| json!({ "record": group_record(g), "incomplete": false }))
+                .collect();
+            return Ok(Reply::Values(records));
+        }
+    };
+
+    Ok(Reply::Values(vec![json!({
+        "record": group_record(&group),
+        "incomplete": false,
+    })]))
+}
+
+async fn get_memberships(peer: &Peer, params: &LookupParams, more: bool) -> Result<Reply, Error> {
+    if params.service != SERVICE {
+        return Ok(Reply::Error(ERR_BAD_SERVICE, json!({})));
+    }
+    // only a check for a single membership can ever result in exactly one reply
+    if !more && (params.user_name.is_none() || params.group_name.is_none()) {
+        return Ok(Reply::Error(ERR_EXPECTED_MORE, json!({})));
+    }
+    if (params.user_name.is_none() || params.group_name.is_none())
+        && peer.check_getent(&Getent::Groups).await.is_err()
+    {
+        return Ok(Reply::Error(ERR_ENUMERATION, json!({})));
+    }
+
+    let memberships = groups()
+        .await?
+        .iter()
+        .filter(|g| params.group_name.as_ref().is_none_or(|n| *n == g.name))
+        .flat_map(|g| {
+            g.members
+                .iter()
+                .filter(|m| params.user_name.as_ref().is_none_or(|n| n == *m))
+                .map(|m| json!({ "userName": m, "groupName": g.name }))
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Reply::Values(memberships))
+}
+
+fn user_record(user: &UserResponse, groups: &[GroupResponse]) -> Value {
+    let member_of = groups
+        .iter()
+        .filter(|g| g.members.contains(&user.name))
+        .map(|g| g.name.as_str())
+        .collect::<Vec<_>>();
+
+    json!({
+        "userName": user.name,
+        "uid": user.id,
+        "gid": user.gid,
+        "realName": user.email,
+        "emailAddress": user.email,
+        "homeDirectory": format!("/home/{}", user.name),
+        "shell": user.shell,
+        "memberOf": member_of,
+        "disposition": "regular",
+        "service": SERVICE,
+    })
+}
+
+fn group_record(group: &GroupResponse) -> Value {
+    json!({
+        "groupName": group.name,
+        "gid": group.id,
+        "members": group.members,
+        "disposition": "regular",
+        "service": SERVICE,
+    })
+}
+
+/// Converts a reply into `NUL` terminated varlink messages.
+fn encode(reply: Reply) -> Vec<Vec<u8>> {
+    let msgs = match reply {
+        Reply::Values(values) if values.is_empty() => {
+            vec![json!({ "error": ERR_NO_RECORD, "parameters": {} })]
+        }
+        Reply::Values(values) => {
+            let last = values.len() - 1;
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(i, parameters)| {
+                    if i < last {
+                        json!({ "parameters": parameters, "continues": true })
+                    } else {
+                        json!({ "parameters": parameters })
+                    }
+                })
+                .collect()
+        }
+        Reply::Error(error, parameters) => {
+            vec![json!({ "error": error, "parameters": parameters })]
+        }
+    };
+
+    msgs.into_iter()
+        .map(|msg| {
+            let mut bytes = msg.to_string().into_bytes();
+            bytes.push(0);
+            bytes
+        })
+        .collect()
+}
+
+/// `None` for values that do not exist or collide with local ones.
+async fn lookup(getent: Getent) -> Result<Option<GetentResponse>, Error> {
+    match getent_bytes(getent).await {
+        Ok(bytes) => Ok(Some(deserialize::<GetentResponse>(&bytes)?)),
+        Err(err) if err.error == ErrorType::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+async fn user(getent: Getent) -> Result<Option<UserResponse>, Error> {
+    match lookup(getent).await? {
+        Some(GetentResponse::User(user)) => Ok(Some(user)),
+        _ => Ok(None),
+    }
+}
+
+async fn users() -> Result<Vec<UserResponse>, Error> {
+    match lookup(Getent::Users).await? {
+        Some(GetentResponse::Users(users)) => Ok(users),
+        _ => Ok(Vec::new()),
+    }
+}
+
+async fn group(getent: Getent) -> Result<Option<GroupResponse>, Error> {
+    match lookup(getent).await? {
+        Some(GetentResponse::Group(group)) => Ok(Some(group)),
+        _ => Ok(None),
+    }
+}
+
+async fn groups() -> Result<Vec<GroupResponse>, Error> {
+    match lookup(Getent::Groups).await? {
+        Some(GetentResponse::Groups(groups)) => Ok(groups),
+        _ => Ok(Vec::new()),
+    }
+}
@@ -1,10 +1,69 @@
-use crate::api_types::{HostDetailsResponse, HostWhoamiRequest};
+use crate::RAUTHY_HEALTHY;
+use crate::api_types::{HostDetailsResponse, HostPublicResponse, HostWhoamiRequest};
+use crate::cache::Cache;
 use crate::config::Config;
 use crate::error::{Error, ErrorType};
 use crate::http_client::HttpClient;
-use log::info;
+use log::{debug, info};
+use std::sync::atomic::Ordering;
+
+static CACHE_KEY: &str = "$whoami$";
 
 pub async fn whoami() -> Result<(), Error> {
+    let resp = fetch().await?;
+    info!(
+        r#"This Host:
+
+hostname:   {}
+force MFA:  {}
+ips:        {:?}
+aliases:    {:?}
+sudo rules: {}
+notes:      {}
+"#,
+        resp.hostname,
+        resp.force_mfa,
+        resp.ips,
+        resp.aliases,
+        resp.sudo_rules.len(),
+        resp.notes.unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Returns the public host details as TOML, either from cache or freshly fetched.
+pub async fn public() -> Result<String, Error> {
+    if let Some(Some(bytes)) = Cache::get(CACHE_KEY.to_string()).await {
+        debug!("Cache hit");
+        return String::from_utf8(bytes)
+            .map_err(|err| Error::new(ErrorType::Internal, err.to_string()));
+    }
+
+    if !RAUTHY_HEALTHY.load(Ordering::Relaxed) {
+        return Err(Error::new(
+            ErrorType::Connection,
+            "Rauthy unhealthy: whoami",
+        ));
+    }
+
+    let details = fetch().await?;
+    let content = toml::to_string(&HostPublicResponse {
+        hostname: details.hostname,
+        force_mfa: details.force_mfa,
+        aliases: details.aliases,
+    })
+    .map_err(|err| Error::new(ErrorType::Internal, err.to_string()))?;
+    Cache::set(
+        CACHE_KEY.to_string(),
+        Some(content.as_bytes().to_vec()),
+        Config::get().cache_ttl_hosts,
+    )
+    .await;
+
+    Ok(content)
+}
+
+pub async fn fetch() -> Result<HostDetailsResponse, Error> {
     let config = Config::get();
     let url = format!(
         "{}auth/v1/pam/hosts/{}/whoami",
@@ -20,23 +79,7 @@ pub async fn whoami() -> Result<(), Error> {
         .await?;
 
     if res.status().is_success() {
-        let resp = res.json::<HostDetailsResponse>().await?;
-        info!(
-            r#"This Host:
-
-hostname:  {}
-force MFA: {}
-ips:       {:?}
-aliases:   {:?}
-notes:     {}
-"#,
-            resp.hostname,
-            resp.force_mfa,
-            resp.ips,
-            resp.aliases,
-            resp.notes.unwrap_or_default()
-        );
-        Ok(())
+        Ok(res.json::<HostDetailsResponse>().await?)
     } else {
         let text = res.text().await?;
         Err(Error::new(ErrorType::Connection, text))
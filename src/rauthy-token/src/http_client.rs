@@ -0,0 +1,15 @@
+use reqwest::tls::Version;
+use std::time::Duration;
+
+#[inline]
+pub fn build() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(Duration::from_secs(10))
+        .min_tls_version(Version::TLS_1_2)
+        .hickory_dns(true)
+        .use_rustls_tls()
+        .user_agent("Rauthy Token Client")
+        .build()
+        .unwrap()
+}
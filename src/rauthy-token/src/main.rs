@@ -0,0 +1,64 @@
+use crate::token_set::TokenSet;
+use std::path::PathBuf;
+use std::{env, process};
+
+mod http_client;
+mod token_set;
+
+static ENV_TOKEN_SET: &str = "RAUTHY_PAM_TOKEN_SET";
+
+pub fn main() {
+    let id_token = match env::args().nth(1).as_deref() {
+        None | Some("access") => false,
+        Some("id") => true,
+        Some(arg) => {
+            eprintln!("Unknown argument '{arg}', must be one of: access, id");
+            process::exit(1);
+        }
+    };
+
+    if let Err(err) = run(id_token) {
+        eprintln!("Error providing Rauthy session token: {err}");
+        process::exit(1);
+    }
+}
+
+fn run(id_token: bool) -> anyhow::Result<()> {
+    let path = token_set_path()?;
+
+    let mut ts = TokenSet::read(&path)?;
+    if ts.needs_refresh() {
+        ts.refresh()?;
+        ts.save(&path)?;
+    }
+
+    if id_token {
+        match ts.id_token {
+            None => {
+                return Err(anyhow::Error::msg(
+                    "No id_token available - the session token client must request `openid`",
+                ));
+            }
+            Some(token) => println!("{token}"),
+        }
+    } else {
+        println!("{}", ts.access_token);
+    }
+
+    Ok(())
+}
+
+/// The PAM module exports the path on session open. If it has not been exported into this
+/// environment, e.g. inside a `systemd --user` unit, we fall back to the default location.
+fn token_set_path() -> anyhow::Result<PathBuf> {
+    if let Ok(path) = env::var(ENV_TOKEN_SET) {
+        return Ok(PathBuf::from(path));
+    }
+
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => Ok(PathBuf::from(dir).join("rauthy").join("token_set")),
+        Err(_) => Err(anyhow::Error::msg(format!(
+            "Neither {ENV_TOKEN_SET} nor XDG_RUNTIME_DIR are set - not in a Rauthy session?"
+        ))),
+    }
+}
@@ -0,0 +1,115 @@
+use crate::http_client;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Refresh the access token this many seconds before it actually expires, so callers never
+/// receive a token that expires while it is in flight.
+const EXP_LEEWAY: i64 = 30;
+
+/// The session token set, written by the PAM module during session open. The format must
+/// match the one in the PAM module.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenSet {
+    pub token_endpoint: String,
+    pub client_id: String,
+    pub access_token: String,
+    pub access_token_exp: i64,
+    pub id_token: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RefreshResponse {
+    access_token: String,
+    expires_in: i64,
+    id_token: Option<String>,
+    refresh_token: Option<String>,
+}
+
+impl TokenSet {
+    #[inline]
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| anyhow::Error::msg(format!("Cannot read {path:?}: {err}")))?;
+        let slf = toml::from_str::<Self>(&content)?;
+        Ok(slf)
+    }
+
+    #[inline]
+    pub fn needs_refresh(&self) -> bool {
+        self.access_token_exp - EXP_LEEWAY < now()
+    }
+
+    pub fn refresh(&mut self) -> anyhow::Result<()> {
+        let Some(refresh_token) = &self.refresh_token else {
+            return Err(anyhow::Error::msg(
+                "The access token has expired and no refresh token exists - log in again",
+            ));
+        };
+
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "refresh_token")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("refresh_token", refresh_token)
+            .finish();
+
+        let res = http_client::build()
+            .post(&self.token_endpoint)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(ACCEPT, "application/json")
+            .body(body)
+            .send()?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let bytes = res.bytes()?;
+            let err = String::from_utf8_lossy(bytes.as_ref());
+            return Err(anyhow::Error::msg(format!(
+                "Token refresh failed: HTTP {status} - {err}"
+            )));
+        }
+
+        let resp = res.json::<RefreshResponse>()?;
+        self.access_token = resp.access_token;
+        self.access_token_exp = now() + resp.expires_in;
+        if resp.id_token.is_some() {
+            self.id_token = resp.id_token;
+        }
+        // refresh tokens may or may not be rotated, depending on the client config
+        if resp.refresh_token.is_some() {
+            self.refresh_token = resp.refresh_token;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the token set atomically, so a concurrent reader never sees a partial file.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let path_tmp = path.with_extension(format!("{}.tmp", process::id()));
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path_tmp)?;
+        file.write_all(toml::to_string(self)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(path_tmp, path)?;
+
+        Ok(())
+    }
+}
+
+#[inline]
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,40 @@
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+#[cfg(debug_assertions)]
+static PROXY_SOCKET: &str = "/tmp/rauthy/rauthy_proxy.sock";
+#[cfg(not(debug_assertions))]
+static PROXY_SOCKET: &str = "/run/rauthy/rauthy_proxy.sock";
+
+/// The public host details from `rauthy-nss`.
+#[derive(Debug, Deserialize)]
+pub struct Host {
+    pub hostname: String,
+    pub force_mfa: bool,
+    pub aliases: Vec<String>,
+}
+
+impl Host {
+    /// A single request is all we ever need, which is not worth an HTTP client dependency.
+    pub fn fetch() -> anyhow::Result<Self> {
+        let mut stream = UnixStream::connect(PROXY_SOCKET).map_err(|err| {
+            anyhow::Error::msg(format!("Cannot connect to {PROXY_SOCKET}: {err}"))
+        })?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        stream.write_all(b"GET /whoami HTTP/1.0\r\nHost: localhost\r\n\r\n")?;
+
+        let mut resp = String::with_capacity(256);
+        stream.read_to_string(&mut resp)?;
+
+        let Some((head, body)) = resp.split_once("\r\n\r\n") else {
+            return Err(anyhow::Error::msg("Invalid response from rauthy-nss"));
+        };
+        if !head.lines().next().unwrap_or_default().contains(" 200 ") {
+            return Err(anyhow::Error::msg(body.trim().to_string()));
+        }
+
+        Ok(toml::from_str::<Self>(body)?)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::host::Host;
+use crate::session::Session;
+use chrono::{DateTime, Utc};
+use std::process;
+
+mod host;
+mod session;
+
+pub fn main() {
+    if let Err(err) = run() {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let session = Session::read()?;
+
+    println!("Rauthy Identity");
+    println!("  username:    {}", session.username);
+    println!("  user_id:     {}", session.user_id);
+    println!("  email:       {}", session.email);
+    println!("  uid / gid:   {} / {}", session.uid, session.gid);
+    println!("  roles:       {}", join(&session.roles));
+    println!("  groups:      {}", join(&session.groups));
+
+    println!("\nSession");
+    println!("  type:        {}", session.session_typ());
+    println!("  token exp:   {}", format_exp(session.token_exp));
+
+    println!("\nHost");
+    match Host::fetch() {
+        Ok(host) => {
+            println!("  hostname:    {}", host.hostname);
+            println!("  aliases:     {}", join(&host.aliases));
+            println!(
+                "  force MFA:   {}",
+                if host.force_mfa { "yes" } else { "no" }
+            );
+        }
+        Err(err) => println!("  unavailable: {err}"),
+    }
+
+    Ok(())
+}
+
+fn join(values: &[String]) -> String {
+    if values.is_empty() {
+        "-".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+fn format_exp(exp: i64) -> String {
+    let Some(dt) = DateTime::<Utc>::from_timestamp(exp, 0) else {
+        return format!("invalid timestamp {exp}");
+    };
+
+    let secs = exp - Utc::now().timestamp();
+    if secs <= 0 {
+        format!("{dt} (expired - a new login is needed for e.g. `sudo`)")
+    } else if secs >= 3600 {
+        format!("{dt} (in {}h {}m)", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{dt} (in {}m {}s)", secs / 60, secs % 60)
+    }
+}
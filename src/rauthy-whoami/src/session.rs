@@ -0,0 +1,47 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::{env, fs};
+
+static ENV_SESSION: &str = "RAUTHY_PAM_SESSION";
+
+/// Written by the PAM module during session open. The format must match the one in the
+/// PAM module.
+#[derive(Debug, Deserialize)]
+pub struct Session {
+    pub user_id: String,
+    pub email: String,
+    pub username: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub roles: Vec<String>,
+    pub groups: Vec<String>,
+    pub token_exp: i64,
+    session_typ: String,
+}
+
+impl Session {
+    pub fn read() -> anyhow::Result<Self> {
+        let path = Self::path();
+        let content = fs::read_to_string(&path).map_err(|err| {
+            anyhow::Error::msg(format!(
+                "Cannot read {path:?}: {err} - not logged in with a Rauthy account?"
+            ))
+        })?;
+        Ok(toml::from_str::<Self>(&content)?)
+    }
+
+    /// Prefers the env var, because it reflects the session this shell belongs to. The file
+    /// always contains the latest session of this user.
+    pub fn session_typ(&self) -> String {
+        env::var(ENV_SESSION).unwrap_or_else(|_| self.session_typ.clone())
+    }
+
+    fn path() -> PathBuf {
+        let dir = match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            // SAFETY: `getuid()` is always successful
+            Err(_) => PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() })),
+        };
+        dir.join("rauthy").join("session")
+    }
+}
@@ -1,10 +1,25 @@
-#/bin/bash
+#!/bin/bash
 
-USERNAME=$1
-USER_ID=$2
-GROUP_ID=$3
-RAUTHY_USER_ID=$4
-RAUTHY_USER_EMAIL=$5
+# All values are passed as env vars:
+# - RAUTHY_PAM_HOOK (session_open / session_close)
+# - RAUTHY_PAM_SERVICE (login, sshd, gdm, ...)
+# - RAUTHY_PAM_USERNAME
+# - RAUTHY_PAM_UID
+# - RAUTHY_PAM_GID
+# - RAUTHY_PAM_USER_ID
+# - RAUTHY_PAM_USER_EMAIL
+# - RAUTHY_PAM_ROLES  (comma separated)
+# - RAUTHY_PAM_GROUPS (comma separated)
+# - RAUTHY_PAM_SESSION_FIRST / RAUTHY_PAM_SESSION_LAST (true / false, unset if unknown)
+# - RAUTHY_PAM_SESSION_COUNT (open sessions of this user after this change)
+#
+# The positional args $1 - $5 (username uid gid user_id email) are deprecated and only
+# still passed for backwards compatibility.
 
 # just an example command - do something useful here
-echo "$(date) $USER_ID:$GROUP_ID $USERNAME $RAUTHY_USER_ID/$RAUTHY_USER_EMAIL - session open" >> /tmp/rauthy_dbg
+echo "$(date) $RAUTHY_PAM_UID:$RAUTHY_PAM_GID $RAUTHY_PAM_USERNAME $RAUTHY_PAM_USER_ID/$RAUTHY_PAM_USER_EMAIL roles=$RAUTHY_PAM_ROLES groups=$RAUTHY_PAM_GROUPS - session open" >> /tmp/rauthy_dbg
+
+# e.g. mount shares only once for the first session
+if [ "$RAUTHY_PAM_SESSION_FIRST" = "true" ]; then
+  echo "$(date) $RAUTHY_PAM_USERNAME - first session" >> /tmp/rauthy_dbg
+fi